@@ -4,7 +4,7 @@ use std::fs::create_dir_all;
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
 use mobix_staking::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use mobix_staking::state::{Config, State, UnbondEntry, UserEntry};
+use mobix_staking::state::{Config, RewardStream, State, UnbondEntry, UserEntry};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -19,4 +19,5 @@ fn main() {
     export_schema(&schema_for!(UnbondEntry), &out_dir);
     export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(State), &out_dir);
+    export_schema(&schema_for!(RewardStream), &out_dir);
 }
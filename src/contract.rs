@@ -1,11 +1,41 @@
 use cosmwasm_std::{
-    attr, entry_point, to_binary, Addr, BankMsg, Binary, Coin, Deps, DepsMut, Env, MessageInfo,
-    Order, Response, StdResult, Uint128, Uint64,
+    attr, entry_point, from_binary, to_binary, Addr, Attribute, BankMsg, Binary, Coin, CosmosMsg,
+    Decimal, Decimal256, Deps, DepsMut, DistributionMsg, Env, FullDelegation, MessageInfo, Order,
+    Response, StakingMsg, StdError, StdResult, Storage, SubMsg, Timestamp, Uint128, Uint256,
+    Uint64, WasmMsg,
 };
+use cw2::{set_contract_version, ContractVersion, CONTRACT};
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg, Denom};
+use cw_storage_plus::Bound;
+use semver::Version;
+use std::convert::TryFrom;
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, UnbondResponse};
-use crate::state::{Config, State, UnbondEntry, UserEntry, CONFIG, STATE, UNBOND_ENTRIES, USERS};
+use crate::migrations::{
+    init_stake_snapshots, init_staker_count, init_unbonding_balance, migrate_from_legacy,
+    rescale_reward_per_token,
+};
+use crate::msg::{
+    DelegationResponse, ExecuteMsg, HooksResponse, InstantiateMsg, InvariantsResponse,
+    LockResponse, MigrateMsg, QueryMsg, ReceiveMsg, RewardReserveResponse, StakeChangedHookMsg,
+    StakersFilter, StakersResponse, StreamRewardResponse, TotalPowerAtHeightResponse,
+    UnbondResponse, VotingPowerAtHeightResponse,
+};
+use crate::state::{
+    BoostCheckpoint, Config, LockEntry, LockTier, PenaltyDestination, RewardRateSegment,
+    RewardStream, State, UnbondEntry, UserEntry, UserStreamEntry, BOOST_CHECKPOINTS,
+    BOOST_EXPIRIES, CONFIG, DELEGATIONS, HOOKS, LOCKS, NEXT_LOCK_ID, NEXT_UNBOND_ID,
+    OWNERSHIP_PROPOSAL, REWARD_STREAMS, STAKER_COUNT, STAKE_SNAPSHOTS, STATE,
+    TOTAL_STAKE_SNAPSHOTS, UNBOND_ENTRIES, USERS, WITHDRAW_ADDRESSES,
+};
+
+// version info for migration
+const CONTRACT_NAME: &str = "crates.io:mobix-staking";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// pagination of the stakers query
+const DEFAULT_LIMIT: u32 = 10;
+const MAX_LIMIT: u32 = 30;
 
 #[entry_point]
 pub fn instantiate(
@@ -14,6 +44,8 @@ pub fn instantiate(
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     let config: Config = Config {
         owner: info.sender.clone(),
         chief_pausing_officer: info.sender, // the owner can change it later
@@ -21,24 +53,211 @@ pub fn instantiate(
         reward_rate: msg.reward_rate,
         paused: msg.paused,
         unbonding_period: msg.unbonding_period,
+        staking_token: msg
+            .staking_token
+            .map(|addr| deps.api.addr_validate(&addr))
+            .transpose()?,
+        lock_tiers: vec![],
+        early_unbond_penalty: Decimal::zero(),
+        penalty_destination: PenaltyDestination::default(),
+        validators: vec![],
+        chain_unbonding_period: Uint64::zero(),
+        share_token: None,
+        transfers_enabled: false,
+        reward_schedule: vec![],
     };
 
     CONFIG.save(deps.storage, &config)?;
 
     let state: State = State {
-        reward_per_token_stored: Uint128::zero(),
+        reward_per_token_stored: Decimal256::zero(),
         last_update_time: env.block.time,
         staked_balance: Uint128::zero(),
+        period_finish: None,
+        reward_budget: Uint128::zero(),
+        boosted_balance: Uint128::zero(),
+        unbonding_balance: Uint128::zero(),
+        reward_reserve: Uint128::zero(),
+        delegated_balance: Uint128::zero(),
+        total_shares: Uint128::zero(),
+        emission_remainder: Uint128::zero(),
+        reward_dust: Uint256::zero(),
+        reward_liabilities: Uint128::zero(),
     };
 
     STATE.save(deps.storage, &state)?;
-
-    Ok(Response::default())
+    REWARD_STREAMS.save(deps.storage, &vec![])?;
+    STAKER_COUNT.save(deps.storage, &0)?;
+    TOTAL_STAKE_SNAPSHOTS.save(deps.storage, &Uint128::zero(), env.block.height)?;
+
+    let attrs = vec![
+        attr("action", "instantiate"),
+        attr("owner", config.owner.clone()),
+        attr("denom", denom_name(&staking_denom(&config))),
+        attr("reward_rate", config.reward_rate),
+        attr("unbonding_period", config.unbonding_period),
+        attr("paused", config.paused.to_string()),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
 }
 
 #[entry_point]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
-    Ok(Default::default())
+pub fn migrate(mut deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let current_version: Version = parse_version(CONTRACT_VERSION)?;
+
+    // the first deployment didn't record its version
+    let stored_version: String = match CONTRACT.may_load(deps.storage)? {
+        Some(ContractVersion { contract, version }) => {
+            if contract != CONTRACT_NAME {
+                return Err(ContractError::InvalidContractName { name: contract });
+            }
+
+            if parse_version(&version)? > current_version {
+                return Err(ContractError::CannotDowngrade {
+                    stored: version,
+                    current: CONTRACT_VERSION.to_string(),
+                });
+            }
+
+            version
+        }
+        None => {
+            migrate_from_legacy(deps.storage)?;
+            "0.1.0".to_string()
+        }
+    };
+
+    init_staker_count(deps.storage)?;
+
+    // versions before 0.3.0 stored reward_per_token as an integer scaled by 10^9
+    if parse_version(&stored_version)? < Version::new(0, 3, 0) {
+        rescale_reward_per_token(deps.storage)?;
+    }
+
+    init_boost_expiries(deps.storage)?;
+
+    // rewards up to now are distributed over the old staked balance
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+    init_unbonding_balance(deps.storage)?;
+    // the snapshots start from the staked balance without the stake that is unbonding
+    init_stake_snapshots(deps.storage, env.block.height)?;
+
+    // versions before 0.3.0 didn't have a reward reserve, later ones may have drained it
+    if parse_version(&stored_version)? < Version::new(0, 3, 0) {
+        init_reward_reserve(deps.branch(), &env)?;
+    }
+
+    // the same goes for the reserves of the reward streams
+    if parse_version(&stored_version)? < Version::new(0, 3, 0) {
+        init_stream_reserves(deps.branch(), &env)?;
+    }
+
+    // versions before 0.3.0 didn't keep track of the rewards owed to the stakers
+    if parse_version(&stored_version)? < Version::new(0, 3, 0) {
+        init_reward_liabilities(deps.branch(), &env)?;
+    }
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let attrs = vec![
+        attr("action", "migrate"),
+        attr("from_version", stored_version),
+        attr("to_version", CONTRACT_VERSION),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// versions before the reward reserve paid rewards out of whatever the contract held on top
+// of the stake, that surplus becomes the reserve
+fn init_reward_reserve(deps: DepsMut, env: &Env) -> Result<(), ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let mut state: State = STATE.load(deps.storage)?;
+    if !state.reward_reserve.is_zero() {
+        return Ok(());
+    }
+
+    let staked_amount: Uint128 = state.staked_balance.checked_add(state.unbonding_balance)?;
+    state.reward_reserve =
+        staking_token_balance(deps.as_ref(), env, &config).saturating_sub(staked_amount);
+
+    STATE.save(deps.storage, &state)?;
+
+    Ok(())
+}
+
+// versions before the stream reserves paid the streams out of whatever the contract held of
+// their token, which becomes the reserve
+fn init_stream_reserves(deps: DepsMut, env: &Env) -> Result<(), ContractError> {
+    let mut streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+    for stream in streams.iter_mut() {
+        if stream.reward_reserve.is_zero() {
+            stream.reward_reserve = denom_balance(deps.as_ref(), env, &stream.denom);
+        }
+    }
+
+    REWARD_STREAMS.save(deps.storage, &streams)?;
+
+    Ok(())
+}
+
+// sums up the rewards of every user once, the state has to be updated to the current time
+fn init_reward_liabilities(deps: DepsMut, env: &Env) -> Result<(), ContractError> {
+    let mut state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+
+    let mut reward_liabilities: Uint128 = Uint128::zero();
+    for item in USERS.range(deps.storage, None, None, Order::Ascending) {
+        let (address, user): (Addr, UserEntry) = item?;
+        let settled: UserEntry =
+            settled_user(deps.as_ref(), env, &address, user, &state, &streams, &[])?;
+        reward_liabilities = reward_liabilities.checked_add(settled.rewards)?;
+    }
+
+    state.reward_liabilities = reward_liabilities;
+    STATE.save(deps.storage, &state)?;
+
+    Ok(())
+}
+
+// versions before the boost of a lock stopped at its unlock time kept it until the lock was
+// released, locks that have ended already stop counting with the migration
+fn init_boost_expiries(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let registered: bool = BOOST_EXPIRIES
+        .keys(storage, None, None, Order::Ascending)
+        .next()
+        .is_some()
+        || BOOST_CHECKPOINTS
+            .keys(storage, None, None, Order::Ascending)
+            .next()
+            .is_some();
+    if registered {
+        return Ok(());
+    }
+
+    let locks: Vec<((Addr, u64), LockEntry)> = LOCKS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+
+    for (_, lock) in locks {
+        let boost: Uint128 = lock_boost(lock.amount, lock.multiplier)?;
+        if boost.is_zero() {
+            continue;
+        }
+
+        BOOST_EXPIRIES.update::<_, ContractError>(
+            storage,
+            lock.unlock_timestamp.u64(),
+            |expiring| Ok(expiring.unwrap_or_default().checked_add(boost)?),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn parse_version(version: &str) -> Result<Version, ContractError> {
+    Version::parse(version).map_err(|err| StdError::generic_err(err.to_string()).into())
 }
 
 // And declare a custom Error variant for the ones where you will want to make use of it
@@ -50,1328 +269,8771 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::AddStake {} => try_add_stake(deps, env, info),
+        ExecuteMsg::AddStake {
+            recipient,
+            lock_duration,
+        } => try_add_stake(deps, env, info, recipient, lock_duration),
+        ExecuteMsg::Receive(msg) => try_receive(deps, env, info, msg),
         ExecuteMsg::Unbond { amount } => try_unbond(deps, env, info, amount),
-        ExecuteMsg::RemoveStake {} => try_remove_stake(deps, env, info),
-        ExecuteMsg::ClaimRewards {} => try_claim(deps, env, info),
-        ExecuteMsg::UpdateConfig { config } => try_update_config(deps, info, config),
+        ExecuteMsg::CancelUnbond { amount } => try_cancel_unbond(deps, env, info, amount),
+        ExecuteMsg::RemoveStake { amount, recipient } => {
+            try_remove_stake(deps, env, info, amount, recipient)
+        }
+        ExecuteMsg::RemoveStakeEarly { amount, recipient } => {
+            try_remove_stake_early(deps, env, info, amount, recipient)
+        }
+        ExecuteMsg::ClaimRewards {
+            recipient,
+            amount,
+            allow_partial,
+        } => try_claim(deps, env, info, recipient, amount, allow_partial),
+        ExecuteMsg::TransferStake {
+            recipient,
+            amount,
+            include_unbonding,
+        } => try_transfer_stake(deps, env, info, recipient, amount, include_unbonding),
+        ExecuteMsg::Compound {} => try_compound(deps, env, info),
+        ExecuteMsg::SetAutoCompound { enabled } => try_set_auto_compound(deps, info, enabled),
+        ExecuteMsg::SetWithdrawAddress { address } => try_set_withdraw_address(deps, info, address),
+        ExecuteMsg::FundRewards {} => try_fund_rewards(deps, info),
+        ExecuteMsg::Harvest {} => try_harvest(deps, env, info),
+        ExecuteMsg::NotifyRewardAmount { duration } => {
+            try_notify_reward_amount(deps, env, info, duration)
+        }
+        ExecuteMsg::SetRewardStream { denom, reward_rate } => {
+            try_set_reward_stream(deps, env, info, denom, reward_rate)
+        }
+        ExecuteMsg::ProposeNewOwner { owner } => try_propose_new_owner(deps, info, owner),
+        ExecuteMsg::AcceptOwnership {} => try_accept_ownership(deps, info),
+        ExecuteMsg::CancelOwnershipProposal {} => try_cancel_ownership_proposal(deps, info),
+        ExecuteMsg::SetChiefPausingOfficer { address } => {
+            try_set_chief_pausing_officer(deps, info, address)
+        }
+        ExecuteMsg::SetRewardRate { reward_rate } => {
+            try_set_reward_rate(deps, env, info, reward_rate)
+        }
+        ExecuteMsg::SetUnbondingPeriod { unbonding_period } => {
+            try_set_unbonding_period(deps, info, unbonding_period)
+        }
+        ExecuteMsg::SetRewardSchedule { reward_schedule } => {
+            try_set_reward_schedule(deps, env, info, reward_schedule)
+        }
+        ExecuteMsg::SetLockTiers { lock_tiers } => try_set_lock_tiers(deps, info, lock_tiers),
+        ExecuteMsg::SetEarlyUnbondPenalty {
+            penalty,
+            destination,
+        } => try_set_early_unbond_penalty(deps, info, penalty, destination),
+        ExecuteMsg::SetValidators {
+            validators,
+            chain_unbonding_period,
+        } => try_set_validators(deps, info, validators, chain_unbonding_period),
+        ExecuteMsg::SetShareToken { address } => try_set_share_token(deps, env, info, address),
+        ExecuteMsg::SetTransfersEnabled { enabled } => {
+            try_set_transfers_enabled(deps, info, enabled)
+        }
+        ExecuteMsg::SweepForeignTokens { denom, recipient } => {
+            try_sweep_foreign_tokens(deps, env, info, denom, recipient)
+        }
+        ExecuteMsg::AddHook { addr } => try_add_hook(deps, info, addr),
+        ExecuteMsg::RemoveHook { addr } => try_remove_hook(deps, info, addr),
+        ExecuteMsg::Pause {} => try_set_paused(deps, info, true),
+        ExecuteMsg::Unpause {} => try_set_paused(deps, info, false),
     }
 }
 
 pub fn try_add_stake(
-    mut deps: DepsMut,
+    deps: DepsMut,
     env: Env,
     info: MessageInfo,
+    recipient: Option<String>,
+    lock_duration: Option<Uint64>,
 ) -> Result<Response, ContractError> {
     let config: Config = CONFIG.load(deps.storage)?;
+    let amount: Uint128 = native_funds(&config, &info)?;
+    let staker: Addr = beneficiary(deps.as_ref(), info.sender.clone(), recipient)?;
 
-    if config.paused {
-        return Err(ContractError::ContractPaused {});
-    }
+    stake(deps, env, info.sender, staker, amount, lock_duration)
+}
 
-    let funds = info
-        .funds
-        .iter()
-        .find(|c| c.denom == config.denom)
-        .ok_or(ContractError::NoFundsAvailable {})?;
+pub fn try_receive(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
 
-    if funds.amount.is_zero() {
+    if wrapper.amount.is_zero() {
         return Err(ContractError::NoFundsAvailable {});
     }
 
-    update_rewards(&mut deps, &env, funds.amount, true)?;
-
-    let state: State = STATE.load(deps.storage)?;
-
-    USERS.update::<_, ContractError>(deps.storage, &info.sender, |record| {
-        // get current state, if there isn't one, get the default state
-        let prev_user_state: UserEntry = record.unwrap_or(UserEntry {
-            amount: Uint128::zero(),
-            rewards: Uint128::zero(),
-            user_reward_per_token_paid: Uint128::zero(),
-        });
-
-        // add the new entry into the record
-        let current_user_state: UserEntry = UserEntry {
-            amount: prev_user_state.amount.checked_add(funds.amount)?,
-            rewards: earned(&prev_user_state, &state, &config, &env)?,
-            user_reward_per_token_paid: state.reward_per_token_stored,
+    let sender: Addr = deps.api.addr_validate(&wrapper.sender)?;
+
+    // the tokens of CW20 reward streams can only fund their stream
+    let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+    let stream_denom: Denom = Denom::Cw20(info.sender.clone());
+    if streams.iter().any(|stream| stream.denom == stream_denom) {
+        return match from_binary(&wrapper.msg)? {
+            ReceiveMsg::FundRewards {} => {
+                fund_reward_stream(deps, sender, stream_denom, wrapper.amount)
+            }
+            _ => Err(ContractError::InvalidToken {}),
         };
+    }
 
-        Ok(current_user_state)
-    })?;
+    // only the configured CW20 token and the share token can be sent to the contract
+    let is_share_token: bool = config.share_token.as_ref() == Some(&info.sender);
+    if !is_share_token && config.staking_token != Some(info.sender) {
+        return Err(ContractError::InvalidToken {});
+    }
 
-    Ok(Response::default().add_attribute("action", "stake"))
+    match from_binary(&wrapper.msg)? {
+        ReceiveMsg::Unbond {} if is_share_token => unbond_shares(deps, env, sender, wrapper.amount),
+        _ if is_share_token => Err(ContractError::InvalidToken {}),
+        ReceiveMsg::Unbond {} => Err(ContractError::InvalidToken {}),
+        ReceiveMsg::Stake {
+            recipient,
+            lock_duration,
+        } => {
+            let staker: Addr = beneficiary(deps.as_ref(), sender.clone(), recipient)?;
+            stake(deps, env, sender, staker, wrapper.amount, lock_duration)
+        }
+        ReceiveMsg::FundRewards {} => fund_rewards(deps, sender, wrapper.amount),
+        ReceiveMsg::NotifyRewardAmount { duration } => {
+            notify_reward_amount(deps, env, sender, wrapper.amount, duration)
+        }
+    }
 }
 
-fn update_rewards(
-    deps: &mut DepsMut,
-    env: &Env,
-    stake_amount: Uint128,
-    is_addition: bool,
-) -> Result<Response, ContractError> {
-    let config: Config = CONFIG.load(deps.storage)?;
-    let prev_state: State = STATE.load(deps.storage)?;
-    let mut new_staked_balance: Uint128 = prev_state.staked_balance;
+// amount of the native staking denom sent along with the message
+fn native_funds(config: &Config, info: &MessageInfo) -> Result<Uint128, ContractError> {
+    if config.staking_token.is_some() {
+        return Err(ContractError::InvalidToken {});
+    }
 
-    if is_addition {
-        new_staked_balance = new_staked_balance.checked_add(stake_amount)?;
-    } else {
-        new_staked_balance = new_staked_balance.checked_sub(stake_amount)?;
+    // other denoms would stay in the contract without being accounted for anywhere
+    if info.funds.iter().any(|c| c.denom != config.denom) || info.funds.len() > 1 {
+        return Err(ContractError::InvalidFunds {});
     }
 
-    let current_state: State = State {
-        reward_per_token_stored: reward_per_token(&prev_state, &config, env)?,
-        last_update_time: env.block.time,
-        staked_balance: new_staked_balance,
-    };
+    let funds = info
+        .funds
+        .first()
+        .ok_or(ContractError::NoFundsAvailable {})?;
 
-    STATE.save(deps.storage, &current_state)?;
+    if funds.amount.is_zero() {
+        return Err(ContractError::NoFundsAvailable {});
+    }
 
-    Ok(Response::default())
+    Ok(funds.amount)
 }
 
-fn reward_per_token(state: &State, config: &Config, env: &Env) -> Result<Uint128, ContractError> {
-    if state.staked_balance.is_zero() {
-        return Ok(state.reward_per_token_stored);
+// for messages that don't take any funds
+fn assert_no_funds(info: &MessageInfo) -> Result<(), ContractError> {
+    if !info.funds.is_empty() {
+        return Err(ContractError::UnexpectedFunds {});
     }
 
-    let current_time: Uint128 = Uint128::from(env.block.time.nanos());
-    let prev_update_time: Uint128 = Uint128::from(state.last_update_time.nanos());
-
-    let delta_time_in_ns: Uint128 = match current_time.checked_sub(prev_update_time) {
-        Ok(res) => res,
-        Err(_) => return Err(ContractError::Numerical {}),
-    };
-    let billion: Uint128 = Uint128::from(10u64.pow(9) as u64);
+    Ok(())
+}
 
-    let delta_time: Uint128 = match delta_time_in_ns.checked_div(billion) {
-        Ok(res) => res,
-        Err(_) => return Err(ContractError::Numerical {}),
-    }; // in seconds
+// the address credited with a stake, the sender unless a recipient is given
+fn beneficiary(deps: Deps, sender: Addr, recipient: Option<String>) -> StdResult<Addr> {
+    match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient),
+        None => Ok(sender),
+    }
+}
 
-    let rewards_per_time: Uint128 = delta_time.checked_mul(config.reward_rate)?;
-    let inflated_rewards_per_time: Uint128 = rewards_per_time.checked_mul(billion)?;
-    let inflated_relative_rewards_per_time: Uint128 =
-        match inflated_rewards_per_time.checked_div(state.staked_balance) {
-            Ok(res) => res,
-            Err(_) => return Err(ContractError::Numerical {}),
-        };
+// the address receiving a user's funds: the given recipient, the user's withdraw address
+// or the user itself, in that order
+fn withdraw_recipient(deps: Deps, user: &Addr, recipient: Option<String>) -> StdResult<Addr> {
+    match recipient {
+        Some(recipient) => deps.api.addr_validate(&recipient),
+        None => Ok(WITHDRAW_ADDRESSES
+            .may_load(deps.storage, user)?
+            .unwrap_or_else(|| user.clone())),
+    }
+}
 
-    Ok(state
-        .reward_per_token_stored
-        .checked_add(inflated_relative_rewards_per_time)?)
+fn denom_name(denom: &Denom) -> String {
+    match denom {
+        Denom::Native(denom) => denom.clone(),
+        Denom::Cw20(token) => token.to_string(),
+    }
 }
 
-fn earned(
-    user: &UserEntry,
-    state: &State,
-    config: &Config,
-    env: &Env,
-) -> Result<Uint128, ContractError> {
-    let reward_per_token: Uint128 = reward_per_token(state, config, env)?;
-    let delta_reward: Uint128 = reward_per_token.checked_sub(user.user_reward_per_token_paid)?;
-    let inflated_relative_delta_reward: Uint128 = user.amount.checked_mul(delta_reward)?;
-    let relative_delta_reward: Uint128 =
-        match inflated_relative_delta_reward.checked_div(Uint128::from(10u64.pow(9))) {
-            Ok(res) => res,
-            Err(_) => return Err(ContractError::Numerical {}),
-        };
-    let total_rewards: Uint128 = relative_delta_reward.checked_add(user.rewards)?;
+// attributes describing a staker's position after a change, so indexers don't have to query it
+fn position_attributes(deps: Deps, config: &Config, staker: &Addr) -> StdResult<Vec<Attribute>> {
+    let user: UserEntry = USERS.may_load(deps.storage, staker)?.unwrap_or_default();
+    let state: State = STATE.load(deps.storage)?;
 
-    Ok(total_rewards)
+    Ok(vec![
+        attr("staker", staker),
+        attr("denom", denom_name(&staking_denom(config))),
+        attr("user_balance", user.amount),
+        attr("total_staked", state.staked_balance),
+        attr(
+            "reward_per_token",
+            state.reward_per_token_stored.to_string(),
+        ),
+    ])
 }
 
-pub fn try_unbond(
+fn staking_denom(config: &Config) -> Denom {
+    match &config.staking_token {
+        Some(token) => Denom::Cw20(token.clone()),
+        None => Denom::Native(config.denom.clone()),
+    }
+}
+
+fn stake(
     mut deps: DepsMut,
     env: Env,
-    info: MessageInfo,
+    sender: Addr,
+    staker: Addr,
     amount: Uint128,
+    lock_duration: Option<Uint64>,
 ) -> Result<Response, ContractError> {
     let config: Config = CONFIG.load(deps.storage)?;
-    let user: UserEntry = USERS.load(deps.storage, &info.sender)?;
 
     if config.paused {
         return Err(ContractError::ContractPaused {});
     }
 
-    if user.amount.is_zero() {
-        return Err(ContractError::NoRecordAvailable {});
-    }
+    let lock_tier: Option<LockTier> = match lock_duration {
+        Some(duration) => Some(
+            config
+                .lock_tiers
+                .iter()
+                .find(|tier| tier.duration == duration)
+                .cloned()
+                .ok_or(ContractError::UnknownLockTier {})?,
+        ),
+        None => None,
+    };
 
-    if amount.is_zero() {
-        return Err(ContractError::ZeroAmountUnbond {});
-    }
+    // with a share token the stake goes into the pool and the staker gets shares of it
+    let position: Addr = match &config.share_token {
+        Some(_) => env.contract.address.clone(),
+        None => staker.clone(),
+    };
 
-    if user.amount.lt(&amount) {
-        return Err(ContractError::InsufficientFunds {});
+    if config.share_token.is_some() && lock_tier.is_some() {
+        return Err(ContractError::LockedLiquidStake {});
     }
 
-    update_rewards(&mut deps, &env, Uint128::zero(), false)?;
+    let (_, mut msgs) = harvest_delegations(&mut deps, &env)?;
+    let mut submsgs: Vec<SubMsg> = auto_compound(&mut deps, &env, &position)?;
+    release_expired_locks(&mut deps, &env, &position)?;
+
+    let shares: Option<Uint128> = match &config.share_token {
+        Some(share_token) => {
+            let shares: Uint128 = shares_for_amount(&mut deps, &env, &position, amount)?;
+            update_total_shares(deps.storage, shares, true)?;
+            msgs.push(
+                WasmMsg::Execute {
+                    contract_addr: share_token.to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Mint {
+                        recipient: staker.to_string(),
+                        amount: shares,
+                    })?,
+                    funds: vec![],
+                }
+                .into(),
+            );
+            Some(shares)
+        }
+        None => None,
+    };
 
-    let state: State = STATE.load(deps.storage)?;
+    update_rewards(&mut deps, &env, amount, true)?;
 
-    let user_updated: UserEntry = UserEntry {
-        amount: user.amount.checked_sub(amount)?,
-        user_reward_per_token_paid: state.reward_per_token_stored,
-        rewards: earned(&user, &state, &config, &env)?,
+    let boost: Uint128 = match &lock_tier {
+        Some(tier) => lock_boost(amount, tier.multiplier)?,
+        None => Uint128::zero(),
     };
+    update_boosted_balance(deps.storage, boost, true)?;
 
-    USERS.update::<_, ContractError>(deps.storage, &info.sender, |_| Ok(user_updated))?;
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
 
-    UNBOND_ENTRIES.update::<_, ContractError>(deps.storage, &info.sender, |prev_state| {
-        let prev_unbond_entry: UnbondEntry = prev_state.unwrap_or(UnbondEntry {
-            unbound_amount: Uint128::zero(),
-            expiration_timestamp: Uint64::zero(),
-            is_valid: false,
-        });
+    let user: UserEntry = USERS.update::<_, ContractError>(deps.storage, &position, |record| {
+        // get current state, if there isn't one, get the default state
+        let prev_user_state: UserEntry = record.unwrap_or_default();
 
-        let billion: Uint64 = Uint64::from(10u64.pow(9) as u64);
-        let current_time: Uint64 = Uint64::from(env.block.time.nanos());
-        let expiration_timestamp: Uint64 =
-            current_time.checked_add(config.unbonding_period.checked_mul(billion)?)?;
-        let unbond_entry: UnbondEntry = UnbondEntry {
-            unbound_amount: amount.checked_add(prev_unbond_entry.unbound_amount)?,
-            expiration_timestamp,
-            is_valid: true,
+        // add the new entry into the record
+        let current_user_state: UserEntry = UserEntry {
+            amount: prev_user_state.amount.checked_add(amount)?,
+            rewards: earned(&prev_user_state, &state, &config, &env)?,
+            user_reward_per_token_paid: state.reward_per_token_stored,
+            stream_rewards: earned_streams(&prev_user_state, &streams)?,
+            auto_compound: prev_user_state.auto_compound,
+            boost: prev_user_state.boost.checked_add(boost)?,
         };
 
-        Ok(unbond_entry)
+        Ok(current_user_state)
     })?;
 
-    Ok(Response::default().add_attribute("action", "unbond"))
-}
-
-pub fn try_remove_stake(
-    mut deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-) -> Result<Response, ContractError> {
-    let config: Config = CONFIG.load(deps.storage)?;
-
-    if config.paused {
-        return Err(ContractError::ContractPaused {});
-    }
+    update_staker_count(deps.storage, user.amount.checked_sub(amount)?, user.amount)?;
+    STAKE_SNAPSHOTS.save(deps.storage, &position, &user.amount, env.block.height)?;
+    msgs.extend(delegate(deps.storage, &config, amount)?);
 
-    let unbond_entry: UnbondEntry =
-        UNBOND_ENTRIES
-            .load(deps.storage, &info.sender)
-            .unwrap_or(UnbondEntry {
-                unbound_amount: Uint128::zero(),
-                expiration_timestamp: Uint64::zero(),
-                is_valid: false,
-            });
-    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+    let mut attrs = vec![
+        attr("action", "stake"),
+        attr("sender", sender),
+        attr("amount", amount),
+    ];
 
-    if !unbond_entry.is_valid || unbond_entry.expiration_timestamp.gt(&current_time) {
-        return Err(ContractError::BondedStake {});
+    if let Some(shares) = shares {
+        attrs.push(attr("shares", shares));
+        attrs.push(attr("share_recipient", staker));
     }
 
-    update_rewards(&mut deps, &env, unbond_entry.unbound_amount, false)?;
+    if let Some(tier) = lock_tier {
+        let billion: Uint64 = Uint64::from(10u64.pow(9));
+        let unlock_timestamp: Uint64 = Uint64::from(env.block.time.nanos())
+            .checked_add(tier.duration.checked_mul(billion)?)?;
 
-    UNBOND_ENTRIES.update::<_, ContractError>(deps.storage, &info.sender, |prev_state| {
-        let prev_entry = prev_state.expect("unexpected error, UserEntry should have been found!");
+        let lock_id: u64 = NEXT_LOCK_ID.may_load(deps.storage)?.unwrap_or_default();
+        NEXT_LOCK_ID.save(deps.storage, &(lock_id + 1))?;
 
-        let current_entry: UnbondEntry = UnbondEntry {
-            unbound_amount: Uint128::zero(),
-            expiration_timestamp: prev_entry.expiration_timestamp,
-            is_valid: false,
+        let lock_entry: LockEntry = LockEntry {
+            amount,
+            multiplier: tier.multiplier,
+            unlock_timestamp,
         };
+        LOCKS.save(deps.storage, (&position, lock_id), &lock_entry)?;
+
+        if !boost.is_zero() {
+            BOOST_EXPIRIES.update::<_, ContractError>(
+                deps.storage,
+                unlock_timestamp.u64(),
+                |expiring| Ok(expiring.unwrap_or_default().checked_add(boost)?),
+            )?;
+        }
 
-        Ok(current_entry)
-    })?;
+        attrs.push(attr("lock_id", lock_id.to_string()));
+        attrs.push(attr("unlock_timestamp", unlock_timestamp));
+    }
 
-    let msg = BankMsg::Send {
-        to_address: info.sender.to_string(),
-        amount: vec![Coin {
-            denom: config.denom,
-            amount: unbond_entry.unbound_amount,
-        }],
-    };
+    attrs.extend(position_attributes(deps.as_ref(), &config, &position)?);
 
-    let attrs = vec![attr("action", "withdraw")];
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Stake {
+        addr: position,
+        amount,
+    };
+    submsgs.extend(hook_msgs(deps.storage, hook_msg)?);
 
-    Ok(Response::new().add_attributes(attrs).add_message(msg))
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_messages(msgs)
+        .add_submessages(submsgs))
 }
 
-pub fn try_claim(
-    mut deps: DepsMut,
-    env: Env,
-    info: MessageInfo,
-) -> Result<Response, ContractError> {
-    let user: UserEntry = USERS.load(deps.storage, &info.sender).unwrap_or(UserEntry {
-        amount: Uint128::zero(),
-        rewards: Uint128::zero(),
-        user_reward_per_token_paid: Uint128::zero(),
-    });
-
-    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
-
+// shares of the pool that are worth amount before it is added to the pool
+fn shares_for_amount(
+    deps: &mut DepsMut,
+    env: &Env,
+    pool: &Addr,
+    amount: Uint128,
+) -> Result<Uint128, ContractError> {
+    let (pool_amount, pool_rewards) = pool_holdings(deps, env, pool)?;
+    let pool_value: Uint128 = pool_amount.checked_add(pool_rewards)?;
     let state: State = STATE.load(deps.storage)?;
-    let config: Config = CONFIG.load(deps.storage)?;
-    let payout_amount = earned(&user, &state, &config, &env)?;
 
-    if user.rewards.is_zero() && payout_amount.is_zero() {
-        return Err(ContractError::NoRewardsAvailable {});
+    if state.total_shares.is_zero() || pool_value.is_zero() {
+        return Ok(amount);
     }
 
-    let contract_balance: Coin = deps
-        .querier
-        .query_balance(env.contract.address, "nanomobx".to_string())
-        .unwrap_or(Coin {
-            amount: Uint128::zero(),
-            denom: "nanomobx".to_string(),
-        });
-    let total_amount: Uint128 = contract_balance.amount;
-    let staked_amount: Uint128 = state.staked_balance;
-    let available_funds: Uint128 = total_amount
-        .checked_sub(staked_amount)
-        .map_err(|_| ContractError::NoFundsAvailable {})?;
+    Ok(amount.multiply_ratio(state.total_shares, pool_value))
+}
 
-    if user.rewards.gt(&available_funds) {
-        return Err(ContractError::NoFundsAvailable {});
-    }
+// stake and rewards of the pool, rewards are only left when the reserve couldn't compound
+// them and still belong to the share holders
+fn pool_holdings(
+    deps: &mut DepsMut,
+    env: &Env,
+    pool: &Addr,
+) -> Result<(Uint128, Uint128), ContractError> {
+    update_rewards(deps, env, Uint128::zero(), true)?;
 
-    if payout_amount.gt(&available_funds) {
-        return Err(ContractError::NoFundsAvailable {});
-    }
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+    let pool_entry: UserEntry = USERS.may_load(deps.storage, pool)?.unwrap_or_default();
 
-    USERS.update::<_, ContractError>(deps.storage, &info.sender, |record| {
-        let prev_user_state: UserEntry = record.ok_or(ContractError::InvalidState {})?;
-        let new_user_state: UserEntry = UserEntry {
-            amount: prev_user_state.amount,
-            rewards: Uint128::zero(),
-            user_reward_per_token_paid: state.reward_per_token_stored,
-        };
+    let pool_rewards: Uint128 = earned(&pool_entry, &state, &config, env)?;
 
-        Ok(new_user_state)
-    })?;
+    Ok((pool_entry.amount, pool_rewards))
+}
+
+// moves rewards of the pool to a share holder, who can claim them once the reserve covers them
+fn transfer_pool_rewards(
+    deps: &mut DepsMut,
+    env: &Env,
+    pool: &Addr,
+    holder: &Addr,
+    rewards: Uint128,
+) -> Result<(), ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
 
-    let msg = BankMsg::Send {
-        to_address: info.sender.to_string(),
-        amount: vec![Coin {
-            denom: config.denom,
-            amount: payout_amount,
-        }],
+    let pool_entry: UserEntry = USERS.load(deps.storage, pool)?;
+    let pool_updated: UserEntry = UserEntry {
+        rewards: earned(&pool_entry, &state, &config, env)?.checked_sub(rewards)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&pool_entry, &streams)?,
+        ..pool_entry
     };
+    USERS.save(deps.storage, pool, &pool_updated)?;
 
-    let attrs = vec![attr("action", "claim")];
+    let holder_entry: UserEntry = USERS.may_load(deps.storage, holder)?.unwrap_or_default();
+    let holder_updated: UserEntry = UserEntry {
+        rewards: earned(&holder_entry, &state, &config, env)?.checked_add(rewards)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&holder_entry, &streams)?,
+        ..holder_entry
+    };
+    USERS.save(deps.storage, holder, &holder_updated)?;
 
-    Ok(Response::new().add_attributes(attrs).add_message(msg))
+    Ok(())
 }
 
-pub fn try_update_config(
-    deps: DepsMut,
-    info: MessageInfo,
-    potential_new_config: Config,
-) -> Result<Response, ContractError> {
-    let old_config: Config = CONFIG.load(deps.storage)?;
-
-    if old_config.owner == info.sender {
-        // the owner can change all configs
-        CONFIG.save(deps.storage, &potential_new_config)?;
-    } else if old_config.chief_pausing_officer == info.sender {
-        // the "pausing_officer" can only change who the pausing officer is
-        // and also whether the contract is paused or not
-        let new_config: Config = Config {
-            owner: old_config.owner,
-            chief_pausing_officer: potential_new_config.chief_pausing_officer,
-            denom: old_config.denom,
-            reward_rate: old_config.reward_rate,
-            paused: potential_new_config.paused,
-            unbonding_period: old_config.unbonding_period,
-        };
-
-        CONFIG.save(deps.storage, &new_config)?;
+fn update_total_shares(
+    storage: &mut dyn Storage,
+    shares: Uint128,
+    is_addition: bool,
+) -> Result<(), ContractError> {
+    let mut state: State = STATE.load(storage)?;
+
+    if is_addition {
+        state.total_shares = state.total_shares.checked_add(shares)?;
     } else {
-        return Err(ContractError::Unauthorized {});
+        state.total_shares = state.total_shares.checked_sub(shares)?;
+    }
+
+    STATE.save(storage, &state)?;
+
+    Ok(())
+}
+
+// extra reward weight of a locked amount on top of the amount itself
+fn lock_boost(amount: Uint128, multiplier: Decimal) -> Result<Uint128, ContractError> {
+    Ok((amount * multiplier).checked_sub(amount)?)
+}
+
+// has to be called after update_rewards, so that the rewards up to now use the previous weight
+fn update_boosted_balance(
+    storage: &mut dyn Storage,
+    boost: Uint128,
+    is_addition: bool,
+) -> Result<(), ContractError> {
+    if boost.is_zero() {
+        return Ok(());
+    }
+
+    let mut state: State = STATE.load(storage)?;
+    if is_addition {
+        state.boosted_balance = state.boosted_balance.checked_add(boost)?;
+    } else {
+        state.boosted_balance = state.boosted_balance.checked_sub(boost)?;
+    }
+
+    STATE.save(storage, &state)?;
+
+    Ok(())
+}
+
+fn update_unbonding_balance(
+    storage: &mut dyn Storage,
+    amount: Uint128,
+    is_addition: bool,
+) -> Result<(), ContractError> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    let mut state: State = STATE.load(storage)?;
+    if is_addition {
+        state.unbonding_balance = state.unbonding_balance.checked_add(amount)?;
+    } else {
+        state.unbonding_balance = state.unbonding_balance.checked_sub(amount)?;
+    }
+
+    STATE.save(storage, &state)?;
+
+    Ok(())
+}
+
+// removes the user's locks that have ended, the user is settled up to the unlock time of each
+// of them, from when on their boost doesn't count anymore
+fn release_expired_locks(
+    deps: &mut DepsMut,
+    env: &Env,
+    address: &Addr,
+) -> Result<(), ContractError> {
+    let expired_locks: Vec<(u64, LockEntry)> = expired_lock_entries(deps.as_ref(), env, address)?;
+
+    if expired_locks.is_empty() {
+        return Ok(());
+    }
+
+    // stores the checkpoints of the locks that have ended since the last update
+    update_rewards(deps, env, Uint128::zero(), true)?;
+
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+    let user: UserEntry = USERS.load(deps.storage, address)?;
+    let user: UserEntry = settle_expired_locks(deps.storage, user, &expired_locks, &[])?;
+
+    for (id, lock) in expired_locks {
+        LOCKS.remove(deps.storage, (address, id));
+
+        let boost: Uint128 = lock_boost(lock.amount, lock.multiplier)?;
+        if boost.is_zero() {
+            continue;
+        }
+
+        let unlock_time: u64 = lock.unlock_timestamp.u64();
+        let mut checkpoint: BoostCheckpoint = BOOST_CHECKPOINTS.load(deps.storage, unlock_time)?;
+        checkpoint.unreleased_boost = checkpoint.unreleased_boost.checked_sub(boost)?;
+        if checkpoint.unreleased_boost.is_zero() {
+            BOOST_CHECKPOINTS.remove(deps.storage, unlock_time);
+        } else {
+            BOOST_CHECKPOINTS.save(deps.storage, unlock_time, &checkpoint)?;
+        }
+    }
+
+    let user_updated: UserEntry = UserEntry {
+        amount: user.amount,
+        rewards: earned(&user, &state, &config, env)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&user, &streams)?,
+        auto_compound: user.auto_compound,
+        boost: user.boost,
+    };
+
+    USERS.save(deps.storage, address, &user_updated)?;
+
+    Ok(())
+}
+
+// settles the user up to the checkpoint of each ended lock, in the order they ended, and drops
+// their boost, checkpoints that haven't been stored yet are taken from pending_checkpoints
+fn settle_expired_locks(
+    storage: &dyn Storage,
+    user: UserEntry,
+    expired_locks: &[(u64, LockEntry)],
+    pending_checkpoints: &[(u64, BoostCheckpoint)],
+) -> Result<UserEntry, ContractError> {
+    let mut expired_boosts: Vec<(u64, Uint128)> = expired_locks
+        .iter()
+        .map(|(_, lock)| {
+            Ok((
+                lock.unlock_timestamp.u64(),
+                lock_boost(lock.amount, lock.multiplier)?,
+            ))
+        })
+        .collect::<Result<_, ContractError>>()?;
+    expired_boosts.sort();
+
+    let mut user: UserEntry = user;
+    for (unlock_time, boost) in expired_boosts {
+        if boost.is_zero() {
+            continue;
+        }
+
+        let checkpoint: BoostCheckpoint = match pending_checkpoints
+            .iter()
+            .find(|(time, _)| *time == unlock_time)
+        {
+            Some((_, checkpoint)) => checkpoint.clone(),
+            None => BOOST_CHECKPOINTS.load(storage, unlock_time)?,
+        };
+
+        // a user settled after the checkpoint already got the rewards up to it
+        let weight: Uint128 = user_weight(&user)?;
+        if checkpoint
+            .reward_per_token
+            .gt(&user.user_reward_per_token_paid)
+        {
+            user.rewards = user.rewards.checked_add(accrued_rewards(
+                weight,
+                checkpoint.reward_per_token,
+                user.user_reward_per_token_paid,
+            )?)?;
+            user.user_reward_per_token_paid = checkpoint.reward_per_token;
+        }
+
+        for (index, reward_per_token) in checkpoint.stream_reward_per_token.iter().enumerate() {
+            if user.stream_rewards.len() <= index {
+                user.stream_rewards.push(UserStreamEntry::default());
+            }
+
+            let entry: &mut UserStreamEntry = &mut user.stream_rewards[index];
+            if reward_per_token.gt(&entry.user_reward_per_token_paid) {
+                entry.rewards = entry.rewards.checked_add(accrued_rewards(
+                    weight,
+                    *reward_per_token,
+                    entry.user_reward_per_token_paid,
+                )?)?;
+                entry.user_reward_per_token_paid = *reward_per_token;
+            }
+        }
+
+        user.boost = user.boost.checked_sub(boost)?;
+    }
+
+    Ok(user)
+}
+
+fn expired_lock_entries(deps: Deps, env: &Env, address: &Addr) -> StdResult<Vec<(u64, LockEntry)>> {
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+
+    Ok(lock_entries(deps, address)?
+        .into_iter()
+        .filter(|(_, lock)| lock.unlock_timestamp.le(&current_time))
+        .collect())
+}
+
+fn lock_entries(deps: Deps, address: &Addr) -> StdResult<Vec<(u64, LockEntry)>> {
+    LOCKS
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect()
+}
+
+// keeps STAKER_COUNT in line with the number of users that have bonded stake
+fn update_staker_count(
+    storage: &mut dyn Storage,
+    prev_amount: Uint128,
+    new_amount: Uint128,
+) -> StdResult<()> {
+    if prev_amount.is_zero() == new_amount.is_zero() {
+        return Ok(());
+    }
+
+    let staker_count: u64 = STAKER_COUNT.may_load(storage)?.unwrap_or_default();
+    if new_amount.is_zero() {
+        STAKER_COUNT.save(storage, &staker_count.saturating_sub(1))
+    } else {
+        STAKER_COUNT.save(storage, &(staker_count + 1))
+    }
+}
+
+fn update_rewards(
+    deps: &mut DepsMut,
+    env: &Env,
+    stake_amount: Uint128,
+    is_addition: bool,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let prev_state: State = STATE.load(deps.storage)?;
+    let mut new_staked_balance: Uint128 = prev_state.staked_balance;
+
+    if is_addition {
+        new_staked_balance = new_staked_balance.checked_add(stake_amount)?;
+    } else {
+        new_staked_balance = new_staked_balance.checked_sub(stake_amount)?;
+    }
+
+    let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+    let (mut current_state, streams, checkpoints) =
+        advance_rewards(deps.storage, &prev_state, &config, streams, env)?;
+    REWARD_STREAMS.save(deps.storage, &streams)?;
+
+    for (unlock_time, checkpoint) in checkpoints {
+        BOOST_EXPIRIES.remove(deps.storage, unlock_time);
+        BOOST_CHECKPOINTS.save(deps.storage, unlock_time, &checkpoint)?;
+    }
+
+    current_state.staked_balance = new_staked_balance;
+
+    STATE.save(deps.storage, &current_state)?;
+    if new_staked_balance != prev_state.staked_balance {
+        TOTAL_STAKE_SNAPSHOTS.save(deps.storage, &new_staked_balance, env.block.height)?;
+    }
+
+    // segments that have started are accounted for up to now, so they become the reward_rate
+    let started_segments: usize = config
+        .reward_schedule
+        .iter()
+        .take_while(|segment| segment.start_time.le(&env.block.time))
+        .count();
+
+    if started_segments > 0 {
+        let mut config: Config = config;
+        config.reward_rate = config.reward_schedule[started_segments - 1].reward_rate;
+        config.reward_schedule.drain(..started_segments);
+        CONFIG.save(deps.storage, &config)?;
     }
 
     Ok(Response::default())
 }
 
-#[entry_point]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::QueryStake { address } => to_binary(&query_stake(deps, address)?),
-        QueryMsg::QueryRewards { address } => to_binary(&query_rewards(deps, address, env)?),
-        QueryMsg::QueryUnbondEntry { address } => {
-            to_binary(&query_unbond_entries(deps, address, env)?)
+// state, reward streams and the checkpoints of the locks that ended in between
+type AdvancedRewards = (State, Vec<RewardStream>, Vec<(u64, BoostCheckpoint)>);
+
+// the state and streams moved forward to the current block time. The boost of locks that have
+// ended in between stops counting at their unlock time, the checkpoints taken there are returned
+fn advance_rewards(
+    storage: &dyn Storage,
+    state: &State,
+    config: &Config,
+    streams: Vec<RewardStream>,
+    env: &Env,
+) -> Result<AdvancedRewards, ContractError> {
+    let expiries: Vec<(u64, Uint128)> = BOOST_EXPIRIES
+        .range(
+            storage,
+            None,
+            Some(Bound::inclusive_int(env.block.time.nanos())),
+            Order::Ascending,
+        )
+        .collect::<StdResult<_>>()?;
+
+    let mut state: State = state.clone();
+    let mut streams: Vec<RewardStream> = streams;
+    let mut checkpoints: Vec<(u64, BoostCheckpoint)> = vec![];
+    for (unlock_time, boost) in expiries {
+        // locks that ended before the last update, only possible for locks from before the
+        // migration, stop counting at the last update
+        let mut expiry_env: Env = env.clone();
+        expiry_env.block.time = Timestamp::from_nanos(unlock_time).max(state.last_update_time);
+
+        streams = advance_reward_streams(streams, &state, &expiry_env)?;
+        state = accrue_rewards(&state, config, &expiry_env)?;
+        state.last_update_time = expiry_env.block.time;
+        state.boosted_balance = state.boosted_balance.checked_sub(boost)?;
+
+        checkpoints.push((
+            unlock_time,
+            BoostCheckpoint {
+                reward_per_token: state.reward_per_token_stored,
+                stream_reward_per_token: streams
+                    .iter()
+                    .map(|stream| stream.reward_per_token_stored)
+                    .collect(),
+                unreleased_boost: boost,
+            },
+        ));
+    }
+
+    let streams: Vec<RewardStream> = advance_reward_streams(streams, &state, env)?;
+    let mut state: State = accrue_rewards(&state, config, env)?;
+    state.last_update_time = env.block.time;
+
+    Ok((state, streams, checkpoints))
+}
+
+// stake that rewards are distributed over, locked stake counts with its multiplier
+fn total_weight(state: &State) -> StdResult<Uint128> {
+    Ok(state.staked_balance.checked_add(state.boosted_balance)?)
+}
+
+fn user_weight(user: &UserEntry) -> StdResult<Uint128> {
+    Ok(user.amount.checked_add(user.boost)?)
+}
+
+fn reward_per_token(
+    state: &State,
+    config: &Config,
+    env: &Env,
+) -> Result<Decimal256, ContractError> {
+    Ok(accrue_rewards(state, config, env)?.reward_per_token_stored)
+}
+
+// the state with the rewards emitted since the last update distributed, rewards emitted while
+// nothing is staked are not distributed, so they stay in the budget
+fn accrue_rewards(state: &State, config: &Config, env: &Env) -> Result<State, ContractError> {
+    let mut accrued: State = state.clone();
+    if state.staked_balance.is_zero() {
+        return Ok(accrued);
+    }
+
+    let (emitted, emission_remainder) = emitted_rewards(state, config, env)?;
+    if state.period_finish.is_some() {
+        accrued.reward_budget = accrued.reward_budget.checked_sub(emitted)?;
+    }
+    accrued.emission_remainder = emission_remainder;
+    credit_rewards(&mut accrued, emitted)?;
+
+    Ok(accrued)
+}
+
+// adds amount per unit of weight to reward_per_token, the part that can't be represented
+// is kept as dust and added to the next amount
+fn credit_rewards(state: &mut State, amount: Uint128) -> Result<(), ContractError> {
+    let weight: Uint128 = total_weight(state)?;
+    if weight.is_zero() {
+        return Ok(());
+    }
+
+    let atomics_per_token: Uint256 = Uint256::from(10u64).pow(18);
+    let (reward_per_token, reward_dust) = add_per_weight(
+        state.reward_per_token_stored,
+        state.reward_dust,
+        Uint256::from(amount).checked_mul(atomics_per_token)?,
+        weight,
+    )?;
+    state.reward_per_token_stored = reward_per_token;
+    state.reward_dust = reward_dust;
+    state.reward_liabilities = state.reward_liabilities.checked_add(amount)?;
+
+    Ok(())
+}
+
+// reward_per_token increased by inflated_amount, in 10^-18 of a token, per unit of weight and
+// the new dust, the part of inflated_amount and dust that can't be represented
+fn add_per_weight(
+    reward_per_token: Decimal256,
+    dust: Uint256,
+    inflated_amount: Uint256,
+    weight: Uint128,
+) -> Result<(Decimal256, Uint256), ContractError> {
+    let weight: Uint256 = Uint256::from(weight);
+    let inflated_amount: Uint256 = inflated_amount.checked_add(dust)?;
+    let inflated_relative_amount: Uint256 = match inflated_amount.checked_div(weight) {
+        Ok(res) => res,
+        Err(_) => return Err(ContractError::Numerical {}),
+    };
+
+    let dust: Uint256 =
+        inflated_amount.checked_sub(inflated_relative_amount.checked_mul(weight)?)?;
+    let reward_per_token: Decimal256 = Decimal256::from_atomics(
+        reward_per_token
+            .atomics()
+            .checked_add(inflated_relative_amount)?,
+        18,
+    )
+    .map_err(|_| ContractError::Numerical {})?;
+
+    Ok((reward_per_token, dust))
+}
+
+// whole rewards of weight for the increase from reward_per_token_paid to reward_per_token
+fn accrued_rewards(
+    weight: Uint128,
+    reward_per_token: Decimal256,
+    reward_per_token_paid: Decimal256,
+) -> Result<Uint128, ContractError> {
+    let delta_reward: Uint256 = reward_per_token
+        .atomics()
+        .checked_sub(reward_per_token_paid.atomics())?;
+    let inflated_relative_delta_reward: Uint256 =
+        Uint256::from(weight).checked_mul(delta_reward)?;
+    let relative_delta_reward: Uint256 =
+        match inflated_relative_delta_reward.checked_div(Uint256::from(10u64).pow(18)) {
+            Ok(res) => res,
+            Err(_) => return Err(ContractError::Numerical {}),
+        };
+
+    Uint128::try_from(relative_delta_reward).map_err(|_| ContractError::Numerical {})
+}
+
+// whole rewards emitted since the last update and the rest of the emission, in nanotokens,
+// which is carried over to the next update. A funded reward program stops emitting at its
+// end or once its budget is used up
+fn emitted_rewards(
+    state: &State,
+    config: &Config,
+    env: &Env,
+) -> Result<(Uint128, Uint128), ContractError> {
+    let end_time: Timestamp = match state.period_finish {
+        Some(period_finish) if period_finish.lt(&env.block.time) => period_finish,
+        _ => env.block.time,
+    };
+
+    // the rate changes at the start of every segment of the schedule
+    let mut inflated_rewards: Uint128 = state.emission_remainder;
+    let mut reward_rate: Uint128 = config.reward_rate;
+    let mut accounted_nanos: Uint128 = Uint128::zero();
+    for segment in config.reward_schedule.iter() {
+        if segment.start_time.ge(&end_time) {
+            break;
         }
-        QueryMsg::QueryConfig {} => to_binary(&query_config(deps)?),
-        QueryMsg::QueryState {} => to_binary(&query_state(deps)?),
-        QueryMsg::QueryStakers {} => to_binary(&query_stakers(deps)?),
+
+        let segment_nanos: Uint128 = elapsed_nanos(state, segment.start_time)?;
+        inflated_rewards = inflated_rewards.checked_add(
+            segment_nanos
+                .checked_sub(accounted_nanos)?
+                .checked_mul(reward_rate)?,
+        )?;
+        accounted_nanos = segment_nanos;
+        reward_rate = segment.reward_rate;
+    }
+
+    inflated_rewards = inflated_rewards.checked_add(
+        elapsed_nanos(state, end_time)?
+            .checked_sub(accounted_nanos)?
+            .checked_mul(reward_rate)?,
+    )?;
+
+    let billion: Uint128 = Uint128::from(10u64.pow(9));
+    let rewards: Uint128 = match inflated_rewards.checked_div(billion) {
+        Ok(res) => res,
+        Err(_) => return Err(ContractError::Numerical {}),
+    };
+    let emission_remainder: Uint128 =
+        inflated_rewards.checked_sub(rewards.checked_mul(billion)?)?;
+
+    match state.period_finish {
+        Some(_) if rewards.ge(&state.reward_budget) => Ok((state.reward_budget, Uint128::zero())),
+        _ => Ok((rewards, emission_remainder)),
+    }
+}
+
+// nanoseconds between the last update and end_time
+fn elapsed_nanos(state: &State, end_time: Timestamp) -> Result<Uint128, ContractError> {
+    if end_time.le(&state.last_update_time) {
+        return Ok(Uint128::zero());
+    }
+
+    let current_time: Uint128 = Uint128::from(end_time.nanos());
+    let prev_update_time: Uint128 = Uint128::from(state.last_update_time.nanos());
+
+    match current_time.checked_sub(prev_update_time) {
+        Ok(res) => Ok(res),
+        Err(_) => Err(ContractError::Numerical {}),
+    }
+}
+
+// moves the reward streams forward to the current block time, state is the one from before the update
+fn advance_reward_streams(
+    streams: Vec<RewardStream>,
+    state: &State,
+    env: &Env,
+) -> Result<Vec<RewardStream>, ContractError> {
+    if state.staked_balance.is_zero() {
+        return Ok(streams);
+    }
+
+    // rate * nanoseconds is in 10^-9 of a token
+    let delta_time: Uint256 = Uint256::from(elapsed_nanos(state, env.block.time)?);
+    let atomics_per_nanotoken: Uint256 = Uint256::from(10u64).pow(9);
+
+    streams
+        .into_iter()
+        .map(|stream| {
+            let inflated_rewards: Uint256 = delta_time
+                .checked_mul(Uint256::from(stream.reward_rate))?
+                .checked_mul(atomics_per_nanotoken)?;
+            let (reward_per_token, reward_dust) = add_per_weight(
+                stream.reward_per_token_stored,
+                stream.reward_dust,
+                inflated_rewards,
+                total_weight(state)?,
+            )?;
+
+            Ok(RewardStream {
+                reward_per_token_stored: reward_per_token,
+                reward_dust,
+                ..stream
+            })
+        })
+        .collect()
+}
+
+// settles the user's rewards for every stream, the streams have to be advanced to the current time
+fn earned_streams(
+    user: &UserEntry,
+    streams: &[RewardStream],
+) -> Result<Vec<UserStreamEntry>, ContractError> {
+    streams
+        .iter()
+        .enumerate()
+        .map(|(index, stream)| {
+            // streams added after the user's last interaction start from a zero checkpoint
+            let prev_entry: UserStreamEntry =
+                user.stream_rewards.get(index).cloned().unwrap_or_default();
+            let relative_delta_reward: Uint128 = accrued_rewards(
+                user_weight(user)?,
+                stream.reward_per_token_stored,
+                prev_entry.user_reward_per_token_paid,
+            )?;
+
+            Ok(UserStreamEntry {
+                rewards: relative_delta_reward.checked_add(prev_entry.rewards)?,
+                user_reward_per_token_paid: stream.reward_per_token_stored,
+            })
+        })
+        .collect()
+}
+
+fn earned(
+    user: &UserEntry,
+    state: &State,
+    config: &Config,
+    env: &Env,
+) -> Result<Uint128, ContractError> {
+    let reward_per_token: Decimal256 = reward_per_token(state, config, env)?;
+    let relative_delta_reward: Uint128 = accrued_rewards(
+        user_weight(user)?,
+        reward_per_token,
+        user.user_reward_per_token_paid,
+    )?;
+    let total_rewards: Uint128 = relative_delta_reward.checked_add(user.rewards)?;
+
+    Ok(total_rewards)
+}
+
+pub fn try_unbond(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+
+    assert_no_funds(&info)?;
+
+    let (_, mut msgs) = harvest_delegations(&mut deps, &env)?;
+    let mut submsgs: Vec<SubMsg> = auto_compound(&mut deps, &env, &info.sender)?;
+    let (unbond_id, expiration_timestamp, undelegate_msgs) =
+        unbond(&mut deps, &env, &config, &info.sender, &info.sender, amount)?;
+    msgs.extend(undelegate_msgs);
+
+    let mut attrs = vec![
+        attr("action", "unbond"),
+        attr("sender", info.sender.clone()),
+        attr("amount", amount),
+        attr("unbond_id", unbond_id.to_string()),
+        attr("expiration_timestamp", expiration_timestamp),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Unbond {
+        addr: info.sender,
+        amount,
+    };
+    submsgs.extend(hook_msgs(deps.storage, hook_msg)?);
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_messages(msgs)
+        .add_submessages(submsgs))
+}
+
+// unbonds shares sent back by their holder, the pool stake they are worth goes into a
+// tranche of the holder
+fn unbond_shares(
+    mut deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    shares: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
     }
+
+    let share_token: Addr = config
+        .share_token
+        .clone()
+        .ok_or(ContractError::InvalidToken {})?;
+    let pool: Addr = env.contract.address.clone();
+
+    let (_, mut msgs) = harvest_delegations(&mut deps, &env)?;
+    let mut submsgs: Vec<SubMsg> = auto_compound(&mut deps, &env, &pool)?;
+
+    let state: State = STATE.load(deps.storage)?;
+    if state.total_shares.lt(&shares) {
+        return Err(ContractError::InsufficientFunds {});
+    }
+
+    // the shares are worth their part of the stake and of the rewards left in the pool
+    let (pool_amount, pool_rewards) = pool_holdings(&mut deps, &env, &pool)?;
+    let amount: Uint128 = shares.multiply_ratio(pool_amount, state.total_shares);
+    let rewards: Uint128 = shares.multiply_ratio(pool_rewards, state.total_shares);
+    update_total_shares(deps.storage, shares, false)?;
+
+    if !rewards.is_zero() {
+        transfer_pool_rewards(&mut deps, &env, &pool, &sender, rewards)?;
+    }
+
+    let (unbond_id, expiration_timestamp, undelegate_msgs) =
+        unbond(&mut deps, &env, &config, &pool, &sender, amount)?;
+
+    msgs.push(
+        WasmMsg::Execute {
+            contract_addr: share_token.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Burn { amount: shares })?,
+            funds: vec![],
+        }
+        .into(),
+    );
+    msgs.extend(undelegate_msgs);
+
+    let mut attrs = vec![
+        attr("action", "unbond"),
+        attr("sender", sender),
+        attr("amount", amount),
+        attr("unbond_id", unbond_id.to_string()),
+        attr("expiration_timestamp", expiration_timestamp),
+        attr("shares", shares),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &pool)?);
+
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Unbond { addr: pool, amount };
+    submsgs.extend(hook_msgs(deps.storage, hook_msg)?);
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_messages(msgs)
+        .add_submessages(submsgs))
 }
 
-fn query_stakers(deps: Deps) -> StdResult<Vec<(Addr, UserEntry)>> {
-    let all_stakers: StdResult<Vec<_>> = USERS
-        .range(deps.storage, None, None, Order::Ascending)
-        .collect();
+// moves amount of the bonded stake of position into a new tranche of owner, the delegation
+// rewards have to be harvested and the position auto compounded before
+fn unbond(
+    deps: &mut DepsMut,
+    env: &Env,
+    config: &Config,
+    position: &Addr,
+    owner: &Addr,
+    amount: Uint128,
+) -> Result<(u64, Uint64, Vec<CosmosMsg>), ContractError> {
+    release_expired_locks(deps, env, position)?;
+    let user: UserEntry = USERS.load(deps.storage, position)?;
+
+    if user.amount.is_zero() {
+        return Err(ContractError::NoRecordAvailable {});
+    }
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmountUnbond {});
+    }
+
+    if user.amount.lt(&amount) {
+        return Err(ContractError::InsufficientFunds {});
+    }
+
+    let mut locked_amount: Uint128 = Uint128::zero();
+    for (_, lock) in lock_entries(deps.as_ref(), position)? {
+        locked_amount = locked_amount.checked_add(lock.amount)?;
+    }
+
+    if user.amount.checked_sub(locked_amount)?.lt(&amount) {
+        return Err(ContractError::LockedStake {});
+    }
+
+    // unbonding stake stops earning right away
+    update_rewards(deps, env, amount, false)?;
+    update_unbonding_balance(deps.storage, amount, true)?;
+
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+
+    let user_updated: UserEntry = UserEntry {
+        amount: user.amount.checked_sub(amount)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        rewards: earned(&user, &state, config, env)?,
+        stream_rewards: earned_streams(&user, &streams)?,
+        auto_compound: user.auto_compound,
+        boost: user.boost,
+    };
+
+    update_staker_count(deps.storage, user.amount, user_updated.amount)?;
+    USERS.save(deps.storage, position, &user_updated)?;
+    STAKE_SNAPSHOTS.save(
+        deps.storage,
+        position,
+        &user_updated.amount,
+        env.block.height,
+    )?;
+
+    let billion: Uint64 = Uint64::from(10u64.pow(9));
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+    let expiration_timestamp: Uint64 =
+        current_time.checked_add(config.unbonding_period.checked_mul(billion)?)?;
+
+    // each unbond gets its own tranche which matures independently of the others
+    let unbond_id: u64 = NEXT_UNBOND_ID.may_load(deps.storage)?.unwrap_or_default();
+    NEXT_UNBOND_ID.save(deps.storage, &(unbond_id + 1))?;
+
+    let (undelegated_amount, msgs) = undelegate(deps.storage, config, amount)?;
+
+    let unbond_entry: UnbondEntry = UnbondEntry {
+        unbound_amount: amount,
+        expiration_timestamp,
+        undelegated_amount,
+    };
+    UNBOND_ENTRIES.save(deps.storage, (owner, unbond_id), &unbond_entry)?;
+
+    Ok((unbond_id, expiration_timestamp, msgs))
+}
+
+pub fn try_cancel_unbond(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+
+    assert_no_funds(&info)?;
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmountCancelUnbond {});
+    }
+
+    // matured tranches can only be removed
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+    let unbonding_entries: Vec<(u64, UnbondEntry)> = unbond_entries(deps.as_ref(), &info.sender)?
+        .into_iter()
+        .filter(|(_, entry)| entry.expiration_timestamp.gt(&current_time))
+        .collect();
+
+    let mut unbonding_amount: Uint128 = Uint128::zero();
+    let mut cancellable_amount: Uint128 = Uint128::zero();
+    for (_, entry) in unbonding_entries.iter() {
+        unbonding_amount = unbonding_amount.checked_add(entry.unbound_amount)?;
+        cancellable_amount = cancellable_amount
+            .checked_add(entry.unbound_amount.checked_sub(entry.undelegated_amount)?)?;
+    }
+
+    if unbonding_amount.lt(&amount) {
+        return Err(ContractError::InsufficientFunds {});
+    }
+
+    // undelegated stake is unbonding on the chain, it can't be bonded again before it is back
+    if cancellable_amount.lt(&amount) {
+        return Err(ContractError::UndelegatedStake {});
+    }
+
+    // the most recent tranches are the furthest from maturity, so they are cancelled first
+    let mut remaining: Uint128 = amount;
+    for (id, entry) in unbonding_entries.into_iter().rev() {
+        if remaining.is_zero() {
+            break;
+        }
+
+        let cancelled_amount: Uint128 = entry
+            .unbound_amount
+            .checked_sub(entry.undelegated_amount)?
+            .min(remaining);
+
+        if cancelled_amount == entry.unbound_amount {
+            UNBOND_ENTRIES.remove(deps.storage, (&info.sender, id));
+        } else {
+            let current_entry: UnbondEntry = UnbondEntry {
+                unbound_amount: entry.unbound_amount.checked_sub(cancelled_amount)?,
+                expiration_timestamp: entry.expiration_timestamp,
+                undelegated_amount: entry.undelegated_amount,
+            };
+            UNBOND_ENTRIES.save(deps.storage, (&info.sender, id), &current_entry)?;
+        }
+
+        remaining = remaining.checked_sub(cancelled_amount)?;
+    }
+
+    let mut submsgs: Vec<SubMsg> = auto_compound(&mut deps, &env, &info.sender)?;
+    release_expired_locks(&mut deps, &env, &info.sender)?;
+
+    update_rewards(&mut deps, &env, amount, true)?;
+    update_unbonding_balance(deps.storage, amount, false)?;
+
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+    let user: UserEntry = USERS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+
+    let user_updated: UserEntry = UserEntry {
+        amount: user.amount.checked_add(amount)?,
+        rewards: earned(&user, &state, &config, &env)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&user, &streams)?,
+        auto_compound: user.auto_compound,
+        boost: user.boost,
+    };
+
+    update_staker_count(deps.storage, user.amount, user_updated.amount)?;
+    USERS.save(deps.storage, &info.sender, &user_updated)?;
+    STAKE_SNAPSHOTS.save(
+        deps.storage,
+        &info.sender,
+        &user_updated.amount,
+        env.block.height,
+    )?;
+
+    let mut attrs = vec![
+        attr("action", "cancel_unbond"),
+        attr("sender", info.sender.clone()),
+        attr("amount", amount),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    // the stake is bonded again
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Stake {
+        addr: info.sender,
+        amount,
+    };
+    submsgs.extend(hook_msgs(deps.storage, hook_msg)?);
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_submessages(submsgs))
+}
+
+pub fn try_remove_stake(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Option<Uint128>,
+    recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+
+    assert_no_funds(&info)?;
+
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+    let expired_entries: Vec<(u64, UnbondEntry)> =
+        expired_unbond_entries(deps.as_ref(), &info.sender, current_time)?;
+
+    if expired_entries.is_empty() {
+        return Err(ContractError::BondedStake {});
+    }
+
+    let mut expired_amount: Uint128 = Uint128::zero();
+    for (_, entry) in expired_entries.iter() {
+        expired_amount = expired_amount.checked_add(entry.unbound_amount)?;
+    }
+
+    // without an explicit amount everything that has matured is withdrawn
+    let unbound_amount: Uint128 = amount.unwrap_or(expired_amount);
+
+    if unbound_amount.is_zero() {
+        return Err(ContractError::ZeroAmountRemove {});
+    }
+
+    if unbound_amount.gt(&expired_amount) {
+        return Err(ContractError::NotEnoughExpiredStakeToRemove {});
+    }
+
+    // consume the oldest tranches first, the last one touched may only be partially withdrawn
+    let mut remaining: Uint128 = unbound_amount;
+    for (id, entry) in expired_entries {
+        if remaining.is_zero() {
+            break;
+        }
+
+        if entry.unbound_amount.le(&remaining) {
+            remaining = remaining.checked_sub(entry.unbound_amount)?;
+            UNBOND_ENTRIES.remove(deps.storage, (&info.sender, id));
+        } else {
+            // the chain has released the undelegated part once the tranche has matured
+            let current_entry: UnbondEntry = UnbondEntry {
+                unbound_amount: entry.unbound_amount.checked_sub(remaining)?,
+                expiration_timestamp: entry.expiration_timestamp,
+                undelegated_amount: Uint128::zero(),
+            };
+            UNBOND_ENTRIES.save(deps.storage, (&info.sender, id), &current_entry)?;
+            remaining = Uint128::zero();
+        }
+    }
+
+    update_unbonding_balance(deps.storage, unbound_amount, false)?;
+
+    let recipient: Addr = withdraw_recipient(deps.as_ref(), &info.sender, recipient)?;
+    let msg: CosmosMsg = transfer_msg(&staking_denom(&config), &recipient, unbound_amount)?;
+
+    let mut attrs = vec![
+        attr("action", "withdraw"),
+        attr("sender", info.sender.clone()),
+        attr("amount", unbound_amount),
+        attr("recipient", recipient),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Remove {
+        addr: info.sender,
+        amount: unbound_amount,
+    };
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_message(msg)
+        .add_submessages(hook_msgs(deps.storage, hook_msg)?))
+}
+
+pub fn try_remove_stake_early(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+    recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+
+    assert_no_funds(&info)?;
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmountRemove {});
+    }
+
+    let mut submsgs: Vec<SubMsg> = auto_compound(&mut deps, &env, &info.sender)?;
+    release_expired_locks(&mut deps, &env, &info.sender)?;
+
+    let billion: Uint64 = Uint64::from(10u64.pow(9));
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+    let unbonding_period: Uint64 = config.unbonding_period.checked_mul(billion)?;
+
+    // the oldest tranches are consumed first, they are the closest to maturity
+    let mut remaining: Uint128 = amount;
+    let mut penalty: Uint128 = Uint128::zero();
+    let mut undelegated_amount: Uint128 = Uint128::zero();
+    for (id, entry) in unbond_entries(deps.as_ref(), &info.sender)? {
+        if remaining.is_zero() {
+            break;
+        }
+
+        // the undelegated part of a tranche is only paid out by the chain once it has matured
+        let time_left: Uint64 = entry.expiration_timestamp.saturating_sub(current_time);
+        let entry_undelegated: Uint128 = if time_left.is_zero() {
+            Uint128::zero()
+        } else {
+            entry.undelegated_amount
+        };
+        undelegated_amount = undelegated_amount.checked_add(entry_undelegated)?;
+
+        let removed_amount: Uint128 = entry
+            .unbound_amount
+            .checked_sub(entry_undelegated)?
+            .min(remaining);
+        if removed_amount.is_zero() {
+            continue;
+        }
+
+        penalty = penalty.checked_add(early_unbond_penalty(&config, removed_amount, time_left)?)?;
+
+        if removed_amount == entry.unbound_amount {
+            UNBOND_ENTRIES.remove(deps.storage, (&info.sender, id));
+        } else {
+            let current_entry: UnbondEntry = UnbondEntry {
+                unbound_amount: entry.unbound_amount.checked_sub(removed_amount)?,
+                expiration_timestamp: entry.expiration_timestamp,
+                undelegated_amount: entry_undelegated,
+            };
+            UNBOND_ENTRIES.save(deps.storage, (&info.sender, id), &current_entry)?;
+        }
+
+        remaining = remaining.checked_sub(removed_amount)?;
+    }
+
+    // whatever isn't covered by the tranches comes from the bonded stake, with the full penalty
+    let user: UserEntry = USERS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+
+    if !remaining.is_zero() {
+        if user.amount.lt(&remaining) && !undelegated_amount.is_zero() {
+            return Err(ContractError::UndelegatedStake {});
+        }
+
+        if user.amount.lt(&remaining) {
+            return Err(ContractError::InsufficientFunds {});
+        }
+
+        let mut locked_amount: Uint128 = Uint128::zero();
+        for (_, lock) in lock_entries(deps.as_ref(), &info.sender)? {
+            locked_amount = locked_amount.checked_add(lock.amount)?;
+        }
+
+        if user.amount.checked_sub(locked_amount)?.lt(&remaining) {
+            return Err(ContractError::LockedStake {});
+        }
+
+        penalty =
+            penalty.checked_add(early_unbond_penalty(&config, remaining, unbonding_period)?)?;
+    }
+
+    update_rewards(&mut deps, &env, remaining, false)?;
+    update_unbonding_balance(deps.storage, amount.checked_sub(remaining)?, false)?;
+
+    if !remaining.is_zero() {
+        let state: State = STATE.load(deps.storage)?;
+        let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+
+        // delegated stake can't be paid out before the chain has undelegated it
+        if state.staked_balance.lt(&state.delegated_balance) {
+            return Err(ContractError::DelegatedStake {});
+        }
+
+        let user_updated: UserEntry = UserEntry {
+            amount: user.amount.checked_sub(remaining)?,
+            rewards: earned(&user, &state, &config, &env)?,
+            user_reward_per_token_paid: state.reward_per_token_stored,
+            stream_rewards: earned_streams(&user, &streams)?,
+            auto_compound: user.auto_compound,
+            boost: user.boost,
+        };
+
+        update_staker_count(deps.storage, user.amount, user_updated.amount)?;
+        USERS.save(deps.storage, &info.sender, &user_updated)?;
+        STAKE_SNAPSHOTS.save(
+            deps.storage,
+            &info.sender,
+            &user_updated.amount,
+            env.block.height,
+        )?;
+    }
+
+    let recipient: Addr = withdraw_recipient(deps.as_ref(), &info.sender, recipient)?;
+    let payout_amount: Uint128 = amount.checked_sub(penalty)?;
+
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    if !payout_amount.is_zero() {
+        msgs.push(transfer_msg(
+            &staking_denom(&config),
+            &recipient,
+            payout_amount,
+        )?);
+    }
+
+    if !penalty.is_zero() {
+        msgs.extend(apply_penalty(deps.storage, &config, penalty)?);
+    }
+
+    let mut attrs = vec![
+        attr("action", "remove_stake_early"),
+        attr("sender", info.sender.clone()),
+        attr("amount", amount),
+        attr("penalty", penalty),
+        attr("recipient", recipient),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    // the part that was still bonded leaves the bonded stake on its way out
+    if !remaining.is_zero() {
+        let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Unbond {
+            addr: info.sender.clone(),
+            amount: remaining,
+        };
+        submsgs.extend(hook_msgs(deps.storage, hook_msg)?);
+    }
+
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Remove {
+        addr: info.sender,
+        amount,
+    };
+    submsgs.extend(hook_msgs(deps.storage, hook_msg)?);
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_messages(msgs)
+        .add_submessages(submsgs))
+}
+
+// the penalty decays linearly from the full penalty at a whole unbonding period left to 0
+fn early_unbond_penalty(
+    config: &Config,
+    amount: Uint128,
+    time_left: Uint64,
+) -> Result<Uint128, ContractError> {
+    let billion: Uint64 = Uint64::from(10u64.pow(9));
+    let unbonding_period: Uint64 = config.unbonding_period.checked_mul(billion)?;
+    let full_penalty: Uint128 = amount * config.early_unbond_penalty;
+
+    if time_left.ge(&unbonding_period) {
+        return Ok(full_penalty);
+    }
+
+    Ok(full_penalty.multiply_ratio(time_left.u64(), unbonding_period.u64()))
+}
+
+// has to be called after update_rewards, the redistributed penalty goes to the stake left
+// after the removal
+fn apply_penalty(
+    storage: &mut dyn Storage,
+    config: &Config,
+    penalty: Uint128,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    match &config.penalty_destination {
+        PenaltyDestination::Burn {} => match staking_denom(config) {
+            Denom::Native(denom) => Ok(vec![BankMsg::Burn {
+                amount: vec![Coin {
+                    denom,
+                    amount: penalty,
+                }],
+            }
+            .into()]),
+            Denom::Cw20(token) => Ok(vec![WasmMsg::Execute {
+                contract_addr: token.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Burn { amount: penalty })?,
+                funds: vec![],
+            }
+            .into()]),
+        },
+        PenaltyDestination::Treasury { address } => Ok(vec![transfer_msg(
+            &staking_denom(config),
+            address,
+            penalty,
+        )?]),
+        PenaltyDestination::Redistribute {} => {
+            distribute_rewards(storage, penalty)?;
+
+            Ok(vec![])
+        }
+    }
+}
+
+// adds the amount to the reward reserve and pays it out to the current stakers right away,
+// without any stake it stays in the reserve for later rewards
+fn distribute_rewards(storage: &mut dyn Storage, amount: Uint128) -> Result<(), ContractError> {
+    let mut state: State = STATE.load(storage)?;
+    state.reward_reserve = state.reward_reserve.checked_add(amount)?;
+    credit_rewards(&mut state, amount)?;
+    STATE.save(storage, &state)?;
+
+    Ok(())
+}
+
+// splits the amount evenly between the configured validators, the first one gets the rest
+fn delegate(
+    storage: &mut dyn Storage,
+    config: &Config,
+    amount: Uint128,
+) -> Result<Vec<CosmosMsg>, ContractError> {
+    if config.validators.is_empty() || amount.is_zero() {
+        return Ok(vec![]);
+    }
+
+    let validator_count: Uint128 = Uint128::from(config.validators.len() as u128);
+    let share: Uint128 = amount.multiply_ratio(1u128, validator_count);
+    let rest: Uint128 = amount.checked_sub(share.checked_mul(validator_count)?)?;
+
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    for (index, validator) in config.validators.iter().enumerate() {
+        let delegated_amount: Uint128 = if index == 0 {
+            share.checked_add(rest)?
+        } else {
+            share
+        };
+
+        if delegated_amount.is_zero() {
+            continue;
+        }
+
+        DELEGATIONS.update::<_, ContractError>(storage, validator, |delegation| {
+            Ok(delegation
+                .unwrap_or_default()
+                .checked_add(delegated_amount)?)
+        })?;
+
+        msgs.push(
+            StakingMsg::Delegate {
+                validator: validator.clone(),
+                amount: Coin {
+                    denom: config.denom.clone(),
+                    amount: delegated_amount,
+                },
+            }
+            .into(),
+        );
+    }
+
+    let mut state: State = STATE.load(storage)?;
+    state.delegated_balance = state.delegated_balance.checked_add(amount)?;
+    STATE.save(storage, &state)?;
+
+    Ok(msgs)
+}
+
+// undelegates up to amount, starting with validators that were removed from the config,
+// stake that was never delegated is already in the contract
+fn undelegate(
+    storage: &mut dyn Storage,
+    config: &Config,
+    amount: Uint128,
+) -> Result<(Uint128, Vec<CosmosMsg>), ContractError> {
+    let mut delegations: Vec<(String, Uint128)> = DELEGATIONS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    delegations.sort_by_key(|(validator, _)| config.validators.contains(validator));
+
+    let mut state: State = STATE.load(storage)?;
+    let total_undelegated: Uint128 = amount.min(state.delegated_balance);
+    state.delegated_balance = state.delegated_balance.checked_sub(total_undelegated)?;
+    STATE.save(storage, &state)?;
+
+    let mut remaining: Uint128 = total_undelegated;
+
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    for (validator, delegated_amount) in delegations {
+        if remaining.is_zero() {
+            break;
+        }
+
+        let undelegated_amount: Uint128 = delegated_amount.min(remaining);
+        if undelegated_amount == delegated_amount {
+            DELEGATIONS.remove(storage, &validator);
+        } else {
+            DELEGATIONS.save(
+                storage,
+                &validator,
+                &delegated_amount.checked_sub(undelegated_amount)?,
+            )?;
+        }
+
+        msgs.push(
+            StakingMsg::Undelegate {
+                validator,
+                amount: Coin {
+                    denom: config.denom.clone(),
+                    amount: undelegated_amount,
+                },
+            }
+            .into(),
+        );
+        remaining = remaining.checked_sub(undelegated_amount)?;
+    }
+
+    Ok((total_undelegated, msgs))
+}
+
+// withdraws the staking rewards of every delegation and distributes them to the stakers,
+// it has to run before the delegations change because the chain withdraws them on every change
+fn harvest_delegations(
+    deps: &mut DepsMut,
+    env: &Env,
+) -> Result<(Uint128, Vec<CosmosMsg>), ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let validators: Vec<String> = DELEGATIONS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+
+    let mut harvested: Uint128 = Uint128::zero();
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    for validator in validators {
+        let delegation: Option<FullDelegation> = deps
+            .querier
+            .query_delegation(env.contract.address.clone(), validator.clone())?;
+
+        let rewards: Uint128 = delegation
+            .map(|delegation| {
+                delegation
+                    .accumulated_rewards
+                    .iter()
+                    .filter(|coin| coin.denom == config.denom)
+                    .map(|coin| coin.amount)
+                    .sum()
+            })
+            .unwrap_or_default();
+
+        if rewards.is_zero() {
+            continue;
+        }
+
+        harvested = harvested.checked_add(rewards)?;
+        msgs.push(DistributionMsg::WithdrawDelegatorReward { validator }.into());
+    }
+
+    if !harvested.is_zero() {
+        // the harvest goes to the weight of now, not to boost that has ended since the last update
+        update_rewards(deps, env, Uint128::zero(), true)?;
+        distribute_rewards(deps.storage, harvested)?;
+    }
+
+    Ok((harvested, msgs))
+}
+
+pub fn try_harvest(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let (harvested, msgs) = harvest_delegations(&mut deps, &env)?;
+    let state: State = STATE.load(deps.storage)?;
+
+    let attrs = vec![
+        attr("action", "harvest"),
+        attr("sender", info.sender),
+        attr("amount", harvested),
+        attr(
+            "reward_per_token",
+            state.reward_per_token_stored.to_string(),
+        ),
+        attr("reward_reserve", state.reward_reserve),
+    ];
+
+    Ok(Response::new().add_attributes(attrs).add_messages(msgs))
+}
+
+fn expired_unbond_entries(
+    deps: Deps,
+    address: &Addr,
+    current_time: Uint64,
+) -> StdResult<Vec<(u64, UnbondEntry)>> {
+    UNBOND_ENTRIES
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .filter(|item| match item {
+            Ok((_, entry)) => entry.expiration_timestamp.le(&current_time),
+            Err(_) => true,
+        })
+        .collect()
+}
+
+fn unbond_entries(deps: Deps, address: &Addr) -> StdResult<Vec<(u64, UnbondEntry)>> {
+    UNBOND_ENTRIES
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect()
+}
+
+pub fn try_transfer_stake(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    amount: Uint128,
+    include_unbonding: bool,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+
+    assert_no_funds(&info)?;
+
+    if !config.transfers_enabled {
+        return Err(ContractError::TransfersDisabled {});
+    }
+
+    let recipient: Addr = deps.api.addr_validate(&recipient)?;
+
+    // the contract's own position is the share token pool
+    if recipient == info.sender || recipient == env.contract.address {
+        return Err(ContractError::InvalidRecipient {});
+    }
+
+    if amount.is_zero() && !include_unbonding {
+        return Err(ContractError::ZeroAmountTransfer {});
+    }
+
+    let mut submsgs: Vec<SubMsg> = auto_compound(&mut deps, &env, &info.sender)?;
+    submsgs.extend(auto_compound(&mut deps, &env, &recipient)?);
+    release_expired_locks(&mut deps, &env, &info.sender)?;
+    release_expired_locks(&mut deps, &env, &recipient)?;
+
+    let user: UserEntry = USERS
+        .may_load(deps.storage, &info.sender)?
+        .unwrap_or_default();
+
+    if user.amount.lt(&amount) {
+        return Err(ContractError::InsufficientFunds {});
+    }
+
+    // locks and their boost stay with the sender
+    let mut locked_amount: Uint128 = Uint128::zero();
+    for (_, lock) in lock_entries(deps.as_ref(), &info.sender)? {
+        locked_amount = locked_amount.checked_add(lock.amount)?;
+    }
+
+    if user.amount.checked_sub(locked_amount)?.lt(&amount) {
+        return Err(ContractError::LockedStake {});
+    }
+
+    // the total stake doesn't change, both parties are settled at the current reward_per_token
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+    let receiver: UserEntry = USERS
+        .may_load(deps.storage, &recipient)?
+        .unwrap_or_default();
+
+    let user_updated: UserEntry = UserEntry {
+        amount: user.amount.checked_sub(amount)?,
+        rewards: earned(&user, &state, &config, &env)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&user, &streams)?,
+        auto_compound: user.auto_compound,
+        boost: user.boost,
+    };
+
+    let receiver_updated: UserEntry = UserEntry {
+        amount: receiver.amount.checked_add(amount)?,
+        rewards: earned(&receiver, &state, &config, &env)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&receiver, &streams)?,
+        auto_compound: receiver.auto_compound,
+        boost: receiver.boost,
+    };
+
+    update_staker_count(deps.storage, user.amount, user_updated.amount)?;
+    update_staker_count(deps.storage, receiver.amount, receiver_updated.amount)?;
+    USERS.save(deps.storage, &info.sender, &user_updated)?;
+    USERS.save(deps.storage, &recipient, &receiver_updated)?;
+    STAKE_SNAPSHOTS.save(
+        deps.storage,
+        &info.sender,
+        &user_updated.amount,
+        env.block.height,
+    )?;
+    STAKE_SNAPSHOTS.save(
+        deps.storage,
+        &recipient,
+        &receiver_updated.amount,
+        env.block.height,
+    )?;
+
+    // tranches keep their id and expiration, so they mature as they would have for the sender
+    let mut unbonding_amount: Uint128 = Uint128::zero();
+    if include_unbonding {
+        for (id, entry) in unbond_entries(deps.as_ref(), &info.sender)? {
+            unbonding_amount = unbonding_amount.checked_add(entry.unbound_amount)?;
+            UNBOND_ENTRIES.remove(deps.storage, (&info.sender, id));
+            UNBOND_ENTRIES.save(deps.storage, (&recipient, id), &entry)?;
+        }
+    }
+
+    let mut attrs = vec![
+        attr("action", "transfer_stake"),
+        attr("sender", info.sender.clone()),
+        attr("recipient", recipient.clone()),
+        attr("amount", amount),
+        attr("unbonding_amount", unbonding_amount),
+        attr("recipient_balance", receiver_updated.amount),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    // the bonded stake moves, tranches aren't bonded
+    if !amount.is_zero() {
+        let unbond_msg: StakeChangedHookMsg = StakeChangedHookMsg::Unbond {
+            addr: info.sender,
+            amount,
+        };
+        let stake_msg: StakeChangedHookMsg = StakeChangedHookMsg::Stake {
+            addr: recipient,
+            amount,
+        };
+        submsgs.extend(hook_msgs(deps.storage, unbond_msg)?);
+        submsgs.extend(hook_msgs(deps.storage, stake_msg)?);
+    }
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_submessages(submsgs))
+}
+
+pub fn try_claim(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: Option<String>,
+    amount: Option<Uint128>,
+    allow_partial: bool,
+) -> Result<Response, ContractError> {
+    assert_no_funds(&info)?;
+
+    let recipient: Addr = withdraw_recipient(deps.as_ref(), &info.sender, recipient)?;
+    release_expired_locks(&mut deps, &env, &info.sender)?;
+    let user: UserEntry = USERS.load(deps.storage, &info.sender).unwrap_or_default();
+
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+
+    let state: State = STATE.load(deps.storage)?;
+    let config: Config = CONFIG.load(deps.storage)?;
+    let mut streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+    let earned_amount: Uint128 = earned(&user, &state, &config, &env)?;
+    let stream_entries: Vec<UserStreamEntry> = earned_streams(&user, &streams)?;
+
+    if earned_amount.is_zero() && stream_entries.iter().all(|entry| entry.rewards.is_zero()) {
+        return Err(ContractError::NoRewardsAvailable {});
+    }
+
+    // without an explicit amount all earned rewards are claimed
+    let requested_amount: Uint128 = amount.unwrap_or(earned_amount);
+
+    if requested_amount.gt(&earned_amount) {
+        return Err(ContractError::InsufficientFunds {});
+    }
+
+    let payout_amount: Uint128 = if requested_amount.gt(&state.reward_reserve) {
+        if !allow_partial {
+            return Err(ContractError::NoFundsAvailable {});
+        }
+
+        state.reward_reserve
+    } else {
+        requested_amount
+    };
+
+    // every stream pays what its reserve covers, the rest stays claimable
+    let stream_payouts: Vec<Uint128> = streams
+        .iter()
+        .zip(stream_entries.iter())
+        .map(|(stream, entry)| entry.rewards.min(stream.reward_reserve))
+        .collect();
+
+    if payout_amount.is_zero() && stream_payouts.iter().all(|payout| payout.is_zero()) {
+        return Err(ContractError::NoFundsAvailable {});
+    }
+
+    let outstanding_amount: Uint128 = earned_amount.checked_sub(payout_amount)?;
+    let mut msgs: Vec<CosmosMsg> = vec![];
+
+    if !payout_amount.is_zero() {
+        pay_out_rewards(deps.storage, payout_amount)?;
+
+        msgs.push(transfer_msg(
+            &staking_denom(&config),
+            &recipient,
+            payout_amount,
+        )?);
+    }
+
+    // every stream pays out in its own token
+    for (stream, payout) in streams.iter_mut().zip(stream_payouts.iter()) {
+        if !payout.is_zero() {
+            stream.reward_reserve = stream.reward_reserve.checked_sub(*payout)?;
+            msgs.push(transfer_msg(&stream.denom, &recipient, *payout)?);
+        }
+    }
+    REWARD_STREAMS.save(deps.storage, &streams)?;
+
+    USERS.update::<_, ContractError>(deps.storage, &info.sender, |record| {
+        let prev_user_state: UserEntry = record.ok_or(ContractError::InvalidState {})?;
+        let new_user_state: UserEntry = UserEntry {
+            amount: prev_user_state.amount,
+            rewards: outstanding_amount,
+            user_reward_per_token_paid: state.reward_per_token_stored,
+            stream_rewards: stream_entries
+                .iter()
+                .zip(stream_payouts.iter())
+                .map(|(entry, payout)| {
+                    Ok(UserStreamEntry {
+                        rewards: entry.rewards.checked_sub(*payout)?,
+                        user_reward_per_token_paid: entry.user_reward_per_token_paid,
+                    })
+                })
+                .collect::<Result<_, ContractError>>()?,
+            auto_compound: prev_user_state.auto_compound,
+            boost: prev_user_state.boost,
+        };
+
+        Ok(new_user_state)
+    })?;
+
+    let mut attrs = vec![
+        attr("action", "claim"),
+        attr("sender", info.sender.clone()),
+        attr("paid", payout_amount),
+        attr("outstanding", outstanding_amount),
+        attr("recipient", recipient),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    Ok(Response::new().add_attributes(attrs).add_messages(msgs))
+}
+
+pub fn try_fund_rewards(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    // the token of a native reward stream goes to the reserve of the stream
+    if let [funds] = info.funds.as_slice() {
+        let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+        let denom: Denom = Denom::Native(funds.denom.clone());
+        if streams.iter().any(|stream| stream.denom == denom) {
+            if funds.amount.is_zero() {
+                return Err(ContractError::NoFundsAvailable {});
+            }
+
+            return fund_reward_stream(deps, info.sender, denom, funds.amount);
+        }
+    }
+
+    let amount: Uint128 = native_funds(&config, &info)?;
+
+    fund_rewards(deps, info.sender, amount)
+}
+
+fn fund_reward_stream(
+    deps: DepsMut,
+    sender: Addr,
+    denom: Denom,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let mut streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+    let stream: &mut RewardStream = streams
+        .iter_mut()
+        .find(|stream| stream.denom == denom)
+        .ok_or(ContractError::InvalidRewardDenom {})?;
+
+    stream.reward_reserve = stream.reward_reserve.checked_add(amount)?;
+    let reward_reserve: Uint128 = stream.reward_reserve;
+    REWARD_STREAMS.save(deps.storage, &streams)?;
+
+    let attrs = vec![
+        attr("action", "fund_rewards"),
+        attr("sender", sender),
+        attr("amount", amount),
+        attr("denom", denom_name(&denom)),
+        attr("reward_reserve", reward_reserve),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+fn fund_rewards(deps: DepsMut, sender: Addr, amount: Uint128) -> Result<Response, ContractError> {
+    update_reward_reserve(deps.storage, amount, true)?;
+
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+
+    let attrs = vec![
+        attr("action", "fund_rewards"),
+        attr("sender", sender),
+        attr("amount", amount),
+        attr("denom", denom_name(&staking_denom(&config))),
+        attr("reward_reserve", state.reward_reserve),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+fn update_reward_reserve(
+    storage: &mut dyn Storage,
+    amount: Uint128,
+    is_addition: bool,
+) -> Result<(), ContractError> {
+    let mut state: State = STATE.load(storage)?;
+    if is_addition {
+        state.reward_reserve = state.reward_reserve.checked_add(amount)?;
+    } else {
+        state.reward_reserve = state.reward_reserve.checked_sub(amount)?;
+    }
+
+    STATE.save(storage, &state)?;
+
+    Ok(())
+}
+
+// rewards leave the reserve when they are paid out or compounded, which settles them as well
+fn pay_out_rewards(storage: &mut dyn Storage, amount: Uint128) -> Result<(), ContractError> {
+    let mut state: State = STATE.load(storage)?;
+    state.reward_reserve = state.reward_reserve.checked_sub(amount)?;
+    state.reward_liabilities = state.reward_liabilities.checked_sub(amount)?;
+
+    STATE.save(storage, &state)?;
+
+    Ok(())
+}
+
+// balance of the staking denom held by the contract
+fn staking_token_balance(deps: Deps, env: &Env, config: &Config) -> Uint128 {
+    denom_balance(deps, env, &staking_denom(config))
+}
+
+fn denom_balance(deps: Deps, env: &Env, denom: &Denom) -> Uint128 {
+    match denom {
+        Denom::Cw20(token) => deps
+            .querier
+            .query_wasm_smart::<BalanceResponse>(
+                token,
+                &Cw20QueryMsg::Balance {
+                    address: env.contract.address.to_string(),
+                },
+            )
+            .map(|res| res.balance)
+            .unwrap_or_default(),
+        Denom::Native(denom) => {
+            let contract_balance: Coin = deps
+                .querier
+                .query_balance(env.contract.address.clone(), denom.clone())
+                .unwrap_or(Coin {
+                    amount: Uint128::zero(),
+                    denom: denom.clone(),
+                });
+            contract_balance.amount
+        }
+    }
+}
+
+pub fn try_compound(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.paused {
+        return Err(ContractError::ContractPaused {});
+    }
+
+    assert_no_funds(&info)?;
+
+    let (compounded_amount, submsgs) = compound_rewards(&mut deps, &env, &info.sender)?;
+
+    if compounded_amount.is_zero() {
+        return Err(ContractError::NoRewardsAvailable {});
+    }
+
+    let mut attrs = vec![
+        attr("action", "compound"),
+        attr("sender", info.sender.clone()),
+        attr("amount", compounded_amount),
+    ];
+    attrs.extend(position_attributes(deps.as_ref(), &config, &info.sender)?);
+
+    Ok(Response::new()
+        .add_attributes(attrs)
+        .add_submessages(submsgs))
+}
+
+pub fn try_set_auto_compound(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    USERS.update::<_, ContractError>(deps.storage, &info.sender, |record| {
+        let mut user: UserEntry = record.unwrap_or_default();
+        user.auto_compound = enabled;
+
+        Ok(user)
+    })?;
+
+    let attrs = vec![
+        attr("action", "set_auto_compound"),
+        attr("sender", info.sender),
+        attr("enabled", enabled.to_string()),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_withdraw_address(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: Option<String>,
+) -> Result<Response, ContractError> {
+    let withdraw_address: Addr = match address {
+        Some(address) => deps.api.addr_validate(&address)?,
+        None => info.sender.clone(),
+    };
+
+    if withdraw_address == info.sender {
+        WITHDRAW_ADDRESSES.remove(deps.storage, &info.sender);
+    } else {
+        WITHDRAW_ADDRESSES.save(deps.storage, &info.sender, &withdraw_address)?;
+    }
+
+    let attrs = vec![
+        attr("action", "set_withdraw_address"),
+        attr("sender", info.sender),
+        attr("withdraw_address", withdraw_address),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// compounds for users that opted in, rewards that aren't funded yet are left for later,
+// returns the hook messages for the compounded stake
+fn auto_compound(
+    deps: &mut DepsMut,
+    env: &Env,
+    address: &Addr,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let user: UserEntry = USERS.may_load(deps.storage, address)?.unwrap_or_default();
+
+    if !user.auto_compound {
+        return Ok(vec![]);
+    }
+
+    match compound_rewards(deps, env, address) {
+        Ok((_, submsgs)) => Ok(submsgs),
+        Err(ContractError::NoFundsAvailable {}) => Ok(vec![]),
+        Err(err) => Err(err),
+    }
+}
+
+// moves the user's staking token rewards into their stake, returns the compounded amount and
+// the hook messages for it
+fn compound_rewards(
+    deps: &mut DepsMut,
+    env: &Env,
+    address: &Addr,
+) -> Result<(Uint128, Vec<SubMsg>), ContractError> {
+    if !USERS.has(deps.storage, address) {
+        return Ok((Uint128::zero(), vec![]));
+    }
+
+    release_expired_locks(deps, env, address)?;
+    update_rewards(deps, env, Uint128::zero(), true)?;
+
+    let user: UserEntry = USERS.load(deps.storage, address)?;
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+    let rewards: Uint128 = earned(&user, &state, &config, env)?;
+
+    if rewards.is_zero() {
+        return Ok((rewards, vec![]));
+    }
+
+    if rewards.gt(&state.reward_reserve) {
+        return Err(ContractError::NoFundsAvailable {});
+    }
+
+    // the rewards stay in the contract, as stake instead of reserve
+    update_rewards(deps, env, rewards, true)?;
+    pay_out_rewards(deps.storage, rewards)?;
+
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+
+    let user_updated: UserEntry = UserEntry {
+        amount: user.amount.checked_add(rewards)?,
+        rewards: Uint128::zero(),
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&user, &streams)?,
+        auto_compound: user.auto_compound,
+        boost: user.boost,
+    };
+
+    update_staker_count(deps.storage, user.amount, user_updated.amount)?;
+    USERS.save(deps.storage, address, &user_updated)?;
+    STAKE_SNAPSHOTS.save(
+        deps.storage,
+        address,
+        &user_updated.amount,
+        env.block.height,
+    )?;
+
+    let hook_msg: StakeChangedHookMsg = StakeChangedHookMsg::Stake {
+        addr: address.clone(),
+        amount: rewards,
+    };
+
+    Ok((rewards, hook_msgs(deps.storage, hook_msg)?))
+}
+
+fn transfer_msg(
+    denom: &Denom,
+    recipient: &Addr,
+    amount: Uint128,
+) -> Result<CosmosMsg, ContractError> {
+    match denom {
+        Denom::Native(denom) => Ok(BankMsg::Send {
+            to_address: recipient.to_string(),
+            amount: vec![Coin {
+                denom: denom.clone(),
+                amount,
+            }],
+        }
+        .into()),
+        Denom::Cw20(contract_addr) => Ok(WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount,
+            })?,
+            funds: vec![],
+        }
+        .into()),
+    }
+}
+
+pub fn try_notify_reward_amount(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    duration: Uint64,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let amount: Uint128 = native_funds(&config, &info)?;
+
+    notify_reward_amount(deps, env, info.sender, amount, duration)
+}
+
+fn notify_reward_amount(
+    mut deps: DepsMut,
+    env: Env,
+    sender: Addr,
+    amount: Uint128,
+    duration: Uint64,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.owner != sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if duration.is_zero() {
+        return Err(ContractError::ZeroRewardDuration {});
+    }
+
+    // checkpoint the rewards emitted so far under the previous program
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+
+    // the checkpoint can move started segments of the schedule into the config
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    // the rest of the schedule would change the rate in the middle of the program
+    if !config.reward_schedule.is_empty() {
+        return Err(ContractError::RewardScheduleActive {});
+    }
+
+    let mut state: State = STATE.load(deps.storage)?;
+
+    // whatever is left of the previous program is rolled over into the new one
+    let reward_budget: Uint128 = state.reward_budget.checked_add(amount)?;
+    let reward_rate: Uint128 = match reward_budget.checked_div(Uint128::from(duration)) {
+        Ok(res) => res,
+        Err(_) => return Err(ContractError::Numerical {}),
+    };
+
+    if reward_rate.is_zero() {
+        return Err(ContractError::RewardAmountTooLow {});
+    }
+
+    let period_finish: Timestamp = env.block.time.plus_seconds(duration.u64());
+
+    let previous_reward_rate: Uint128 = config.reward_rate;
+    config.reward_rate = reward_rate;
+    CONFIG.save(deps.storage, &config)?;
+
+    state.period_finish = Some(period_finish);
+    state.reward_budget = reward_budget;
+    state.reward_reserve = state.reward_reserve.checked_add(amount)?;
+    STATE.save(deps.storage, &state)?;
+
+    let attrs = vec![
+        attr("action", "notify_reward_amount"),
+        attr("sender", sender),
+        attr("amount", amount),
+        attr("previous_reward_rate", previous_reward_rate),
+        attr("reward_rate", reward_rate),
+        attr("reward_budget", reward_budget),
+        attr("period_finish", period_finish.nanos().to_string()),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_reward_stream(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: Denom,
+    reward_rate: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    if config.owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let denom: Denom = match denom {
+        Denom::Native(denom) => Denom::Native(denom),
+        Denom::Cw20(contract_addr) => Denom::Cw20(deps.api.addr_validate(contract_addr.as_str())?),
+    };
+
+    // the staking token is already paid out through the main reward rate
+    if denom == staking_denom(&config) {
+        return Err(ContractError::InvalidRewardDenom {});
+    }
+
+    // the old rate applies up to now, the new one only from now on
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+
+    let mut streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.storage)?;
+    let denom_attr: Attribute = attr("denom", denom_name(&denom));
+
+    let previous_reward_rate: Uint128 =
+        match streams.iter_mut().find(|stream| stream.denom == denom) {
+            Some(stream) => std::mem::replace(&mut stream.reward_rate, reward_rate),
+            None => {
+                streams.push(RewardStream {
+                    denom,
+                    reward_rate,
+                    reward_per_token_stored: Decimal256::zero(),
+                    reward_reserve: Uint128::zero(),
+                    reward_dust: Uint256::zero(),
+                });
+                Uint128::zero()
+            }
+        };
+
+    REWARD_STREAMS.save(deps.storage, &streams)?;
+
+    let attrs = vec![
+        attr("action", "set_reward_stream"),
+        attr("sender", info.sender),
+        denom_attr,
+        attr("previous_reward_rate", previous_reward_rate),
+        attr("reward_rate", reward_rate),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+fn assert_owner(config: &Config, sender: &Addr) -> Result<(), ContractError> {
+    if config.owner != *sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    Ok(())
+}
+
+pub fn try_propose_new_owner(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: String,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    // ownership only moves once the new owner accepts, so a wrong address can't lock us out
+    let proposed_owner: Addr = deps.api.addr_validate(&owner)?;
+    OWNERSHIP_PROPOSAL.save(deps.storage, &proposed_owner)?;
+
+    let attrs = vec![
+        attr("action", "propose_new_owner"),
+        attr("sender", info.sender),
+        attr("proposed_owner", proposed_owner),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_accept_ownership(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let proposed_owner: Addr = OWNERSHIP_PROPOSAL
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoOwnershipProposal {})?;
+
+    if proposed_owner != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    let previous_owner: Addr = config.owner;
+    config.owner = proposed_owner;
+    CONFIG.save(deps.storage, &config)?;
+    OWNERSHIP_PROPOSAL.remove(deps.storage);
+
+    let attrs = vec![
+        attr("action", "accept_ownership"),
+        attr("sender", info.sender),
+        attr("previous_owner", previous_owner),
+        attr("owner", config.owner),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_cancel_ownership_proposal(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    if OWNERSHIP_PROPOSAL.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoOwnershipProposal {});
+    }
+
+    OWNERSHIP_PROPOSAL.remove(deps.storage);
+
+    let attrs = vec![
+        attr("action", "cancel_ownership_proposal"),
+        attr("sender", info.sender),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_chief_pausing_officer(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    // the "pausing_officer" can hand over the role as well
+    if config.owner != info.sender && config.chief_pausing_officer != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let previous_officer: Addr = config.chief_pausing_officer;
+    config.chief_pausing_officer = deps.api.addr_validate(&address)?;
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_chief_pausing_officer"),
+        attr("sender", info.sender),
+        attr("previous_chief_pausing_officer", previous_officer),
+        attr("chief_pausing_officer", config.chief_pausing_officer),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_reward_rate(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    reward_rate: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    // the old rate applies up to now, the new one only from now on
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+    end_reward_program(deps.storage, &env)?;
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    let previous_reward_rate: Uint128 = config.reward_rate;
+    config.reward_rate = reward_rate;
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_reward_rate"),
+        attr("sender", info.sender),
+        attr("previous_reward_rate", previous_reward_rate),
+        attr("reward_rate", reward_rate),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_unbonding_period(
+    deps: DepsMut,
+    info: MessageInfo,
+    unbonding_period: Uint64,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    // tranches must not mature before the chain has paid out their undelegated stake
+    if unbonding_period.lt(&config.chain_unbonding_period) {
+        return Err(ContractError::UnbondingPeriodTooShort {});
+    }
+
+    // only applies to unbonds made from now on
+    let previous_unbonding_period: Uint64 = config.unbonding_period;
+    config.unbonding_period = unbonding_period;
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_unbonding_period"),
+        attr("sender", info.sender),
+        attr("previous_unbonding_period", previous_unbonding_period),
+        attr("unbonding_period", unbonding_period),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_reward_schedule(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    reward_schedule: Vec<RewardRateSegment>,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    let mut start_time: Timestamp = env.block.time;
+    for segment in reward_schedule.iter() {
+        if segment.start_time.le(&start_time) {
+            return Err(ContractError::InvalidRewardSchedule {});
+        }
+        start_time = segment.start_time;
+    }
+
+    // segments of the old schedule that have started still apply up to now
+    update_rewards(&mut deps, &env, Uint128::zero(), true)?;
+    end_reward_program(deps.storage, &env)?;
+
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    let previous_reward_schedule: Vec<RewardRateSegment> =
+        std::mem::replace(&mut config.reward_schedule, reward_schedule);
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_reward_schedule"),
+        attr("sender", info.sender),
+        attr("reward_rate", config.reward_rate),
+        attr(
+            "previous_reward_schedule",
+            reward_schedule_string(&previous_reward_schedule),
+        ),
+        attr(
+            "reward_schedule",
+            reward_schedule_string(&config.reward_schedule),
+        ),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// the rate of a funded reward program follows from its budget, so it can only be changed once
+// the program is over, which makes the rate emitted without an end again
+fn end_reward_program(storage: &mut dyn Storage, env: &Env) -> Result<(), ContractError> {
+    let mut state: State = STATE.load(storage)?;
+    match state.period_finish {
+        Some(period_finish) if period_finish.gt(&env.block.time) => {
+            Err(ContractError::RewardProgramActive {})
+        }
+        Some(_) => {
+            // whatever is left of the budget stays in the reward reserve
+            state.period_finish = None;
+            state.reward_budget = Uint128::zero();
+            STATE.save(storage, &state)?;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+// "start_time:reward_rate" pairs with the start time in nanoseconds, e.g. "1700000000000000000:5"
+fn reward_schedule_string(reward_schedule: &[RewardRateSegment]) -> String {
+    let segments: Vec<String> = reward_schedule
+        .iter()
+        .map(|segment| format!("{}:{}", segment.start_time.nanos(), segment.reward_rate))
+        .collect();
+
+    list_string(&segments)
+}
+
+pub fn try_set_lock_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    lock_tiers: Vec<LockTier>,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    for (index, tier) in lock_tiers.iter().enumerate() {
+        if tier.duration.is_zero()
+            || tier.multiplier.lt(&Decimal::one())
+            || lock_tiers[..index]
+                .iter()
+                .any(|other| other.duration == tier.duration)
+        {
+            return Err(ContractError::InvalidLockTier {});
+        }
+    }
+
+    // existing locks keep the multiplier they were created with
+    let previous_lock_tiers: Vec<LockTier> = std::mem::replace(&mut config.lock_tiers, lock_tiers);
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_lock_tiers"),
+        attr("sender", info.sender),
+        attr(
+            "previous_lock_tiers",
+            lock_tiers_string(&previous_lock_tiers),
+        ),
+        attr("lock_tiers", lock_tiers_string(&config.lock_tiers)),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// "duration:multiplier" pairs, e.g. "2592000:1.5,7776000:2"
+fn lock_tiers_string(lock_tiers: &[LockTier]) -> String {
+    let tiers: Vec<String> = lock_tiers
+        .iter()
+        .map(|tier| format!("{}:{}", tier.duration, tier.multiplier))
+        .collect();
+
+    list_string(&tiers)
+}
+
+// comma separated, "none" for an empty list since the chain rejects empty attribute values
+fn list_string(items: &[String]) -> String {
+    if items.is_empty() {
+        return "none".to_string();
+    }
+
+    items.join(",")
+}
+
+pub fn try_set_early_unbond_penalty(
+    deps: DepsMut,
+    info: MessageInfo,
+    penalty: Decimal,
+    destination: PenaltyDestination,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    if penalty.gt(&Decimal::one()) {
+        return Err(ContractError::InvalidPenalty {});
+    }
+
+    if let PenaltyDestination::Treasury { address } = &destination {
+        deps.api.addr_validate(address.as_str())?;
+    }
+
+    let previous_penalty: Decimal = config.early_unbond_penalty;
+    let previous_destination: PenaltyDestination =
+        std::mem::replace(&mut config.penalty_destination, destination);
+    config.early_unbond_penalty = penalty;
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_early_unbond_penalty"),
+        attr("sender", info.sender),
+        attr("previous_penalty", previous_penalty.to_string()),
+        attr("penalty", penalty.to_string()),
+        attr(
+            "previous_penalty_destination",
+            penalty_destination_string(&previous_destination),
+        ),
+        attr(
+            "penalty_destination",
+            penalty_destination_string(&config.penalty_destination),
+        ),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// "burn", "redistribute" or "treasury:<address>"
+fn penalty_destination_string(destination: &PenaltyDestination) -> String {
+    match destination {
+        PenaltyDestination::Burn {} => "burn".to_string(),
+        PenaltyDestination::Treasury { address } => format!("treasury:{}", address),
+        PenaltyDestination::Redistribute {} => "redistribute".to_string(),
+    }
+}
+
+pub fn try_set_validators(
+    deps: DepsMut,
+    info: MessageInfo,
+    validators: Vec<String>,
+    chain_unbonding_period: Uint64,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    if config.staking_token.is_some() && !validators.is_empty() {
+        return Err(ContractError::DelegationUnsupported {});
+    }
+
+    if !validators.is_empty() && deps.querier.query_bonded_denom()? != config.denom {
+        return Err(ContractError::InvalidBondedDenom {});
+    }
+
+    // tranches must not mature before the chain has paid out their undelegated stake
+    if config.unbonding_period.lt(&chain_unbonding_period) {
+        return Err(ContractError::UnbondingPeriodTooShort {});
+    }
+
+    for (index, validator) in validators.iter().enumerate() {
+        if validators[..index].contains(validator)
+            || deps.querier.query_validator(validator)?.is_none()
+        {
+            return Err(ContractError::InvalidValidators {});
+        }
+    }
+
+    // existing delegations aren't moved, they are undelegated first when stake is unbonded
+    let previous_validators: Vec<String> = std::mem::replace(&mut config.validators, validators);
+    config.chain_unbonding_period = chain_unbonding_period;
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_validators"),
+        attr("sender", info.sender),
+        attr("previous_validators", list_string(&previous_validators)),
+        attr("validators", list_string(&config.validators)),
+        attr("chain_unbonding_period", chain_unbonding_period),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// the share token has to be a new CW20 with this contract as its only minter
+pub fn try_set_share_token(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    // shares that are already out would lose their meaning with a different token
+    if config.share_token.is_some() {
+        return Err(ContractError::ShareTokenAlreadySet {});
+    }
+
+    let share_token: Addr = deps.api.addr_validate(&address)?;
+    config.share_token = Some(share_token.clone());
+    CONFIG.save(deps.storage, &config)?;
+
+    // the pool compounds its rewards, which is what grows the exchange rate
+    let pool: Addr = env.contract.address;
+    let mut pool_entry: UserEntry = USERS.may_load(deps.storage, &pool)?.unwrap_or_default();
+    pool_entry.auto_compound = true;
+    USERS.save(deps.storage, &pool, &pool_entry)?;
+
+    let attrs = vec![
+        attr("action", "set_share_token"),
+        attr("sender", info.sender),
+        attr("share_token", share_token),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_set_transfers_enabled(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    let previous_transfers_enabled: bool = config.transfers_enabled;
+    config.transfers_enabled = enabled;
+    CONFIG.save(deps.storage, &config)?;
+
+    let attrs = vec![
+        attr("action", "set_transfers_enabled"),
+        attr("sender", info.sender),
+        attr(
+            "previous_transfers_enabled",
+            previous_transfers_enabled.to_string(),
+        ),
+        attr("transfers_enabled", enabled.to_string()),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// sends coins that were sent to the contract by mistake to recipient, of the staking denom
+// only what isn't stake or reward reserve, the reward stream tokens can't be swept
+pub fn try_sweep_foreign_tokens(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    denom: Denom,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+    assert_no_funds(&info)?;
+
+    let recipient: Addr = deps.api.addr_validate(&recipient)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+    if streams.iter().any(|stream| stream.denom == denom) {
+        return Err(ContractError::InvalidSweepDenom {});
+    }
+
+    let balance: Uint128 = denom_balance(deps.as_ref(), &env, &denom);
+    let amount: Uint128 = if denom == staking_denom(&config) {
+        // delegated stake isn't held by the contract
+        let state: State = STATE.load(deps.storage)?;
+        let held_stake: Uint128 = state
+            .staked_balance
+            .checked_sub(state.delegated_balance)?
+            .checked_add(state.unbonding_balance)?
+            .checked_add(state.reward_reserve)?;
+        balance.saturating_sub(held_stake)
+    } else {
+        balance
+    };
+
+    if amount.is_zero() {
+        return Err(ContractError::NoFundsAvailable {});
+    }
+
+    let msg: CosmosMsg = transfer_msg(&denom, &recipient, amount)?;
+
+    let attrs = vec![
+        attr("action", "sweep_foreign_tokens"),
+        attr("sender", info.sender),
+        attr("denom", denom_name(&denom)),
+        attr("amount", amount),
+        attr("recipient", recipient),
+    ];
+
+    Ok(Response::new().add_attributes(attrs).add_message(msg))
+}
+
+pub fn try_add_hook(
+    deps: DepsMut,
+    info: MessageInfo,
+    addr: String,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    let hook: Addr = deps.api.addr_validate(&addr)?;
+    let mut hooks: Vec<Addr> = HOOKS.may_load(deps.storage)?.unwrap_or_default();
+    if hooks.contains(&hook) {
+        return Err(ContractError::HookAlreadyRegistered {});
+    }
+
+    hooks.push(hook.clone());
+    HOOKS.save(deps.storage, &hooks)?;
+
+    let attrs = vec![
+        attr("action", "add_hook"),
+        attr("sender", info.sender),
+        attr("hook", hook),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+pub fn try_remove_hook(
+    deps: DepsMut,
+    info: MessageInfo,
+    addr: String,
+) -> Result<Response, ContractError> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    assert_owner(&config, &info.sender)?;
+
+    let hook: Addr = deps.api.addr_validate(&addr)?;
+    let mut hooks: Vec<Addr> = HOOKS.may_load(deps.storage)?.unwrap_or_default();
+    if !hooks.contains(&hook) {
+        return Err(ContractError::HookNotRegistered {});
+    }
+
+    hooks.retain(|registered| *registered != hook);
+    HOOKS.save(deps.storage, &hooks)?;
+
+    let attrs = vec![
+        attr("action", "remove_hook"),
+        attr("sender", info.sender),
+        attr("hook", hook),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+// a submessage per registered hook, a failing hook fails the whole transaction
+fn hook_msgs(storage: &dyn Storage, msg: StakeChangedHookMsg) -> StdResult<Vec<SubMsg>> {
+    let hooks: Vec<Addr> = HOOKS.may_load(storage)?.unwrap_or_default();
+    hooks
+        .into_iter()
+        .map(|hook| Ok(SubMsg::new(msg.clone().into_cosmos_msg(hook)?)))
+        .collect()
+}
+
+pub fn try_set_paused(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let mut config: Config = CONFIG.load(deps.storage)?;
+
+    if config.owner != info.sender && config.chief_pausing_officer != info.sender {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let previous_paused: bool = config.paused;
+    config.paused = paused;
+    CONFIG.save(deps.storage, &config)?;
+
+    let action = if paused { "pause" } else { "unpause" };
+    let attrs = vec![
+        attr("action", action),
+        attr("sender", info.sender),
+        attr("previous_paused", previous_paused.to_string()),
+        attr("paused", paused.to_string()),
+    ];
+
+    Ok(Response::new().add_attributes(attrs))
+}
+
+#[entry_point]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::QueryStake { address } => to_binary(&query_stake(deps, address)?),
+        QueryMsg::QueryRewards { address } => to_binary(&query_rewards(deps, address, env)?),
+        QueryMsg::QueryUnbondEntry { address } => {
+            to_binary(&query_unbond_entries(deps, address, env)?)
+        }
+        QueryMsg::QueryWithdrawAddress { address } => {
+            to_binary(&query_withdraw_address(deps, address)?)
+        }
+        QueryMsg::QueryLocks { address } => to_binary(&query_locks(deps, address, env)?),
+        QueryMsg::QueryConfig {} => to_binary(&query_config(deps)?),
+        QueryMsg::QueryOwnershipProposal {} => to_binary(&query_ownership_proposal(deps)?),
+        QueryMsg::QueryState {} => to_binary(&query_state(deps, env)?),
+        QueryMsg::QueryStakers {
+            start_after,
+            limit,
+            filter,
+        } => to_binary(&query_stakers(deps, env, start_after, limit, filter)?),
+        QueryMsg::QueryStakerCount {} => to_binary(&query_staker_count(deps)?),
+        QueryMsg::QueryInvariants {} => to_binary(&query_invariants(deps, env)?),
+        QueryMsg::QueryRewardReserve {} => to_binary(&query_reward_reserve(deps, env)?),
+        QueryMsg::QueryDelegations {} => to_binary(&query_delegations(deps)?),
+        QueryMsg::QueryExchangeRate {} => to_binary(&query_exchange_rate(deps, env)?),
+        QueryMsg::QueryTotalShares {} => to_binary(&query_total_shares(deps)?),
+        QueryMsg::QueryVotingPowerAtHeight { address, height } => {
+            to_binary(&query_voting_power_at_height(deps, env, address, height)?)
+        }
+        QueryMsg::QueryTotalPowerAtHeight { height } => {
+            to_binary(&query_total_power_at_height(deps, env, height)?)
+        }
+        QueryMsg::QueryHooks {} => to_binary(&query_hooks(deps)?),
+        QueryMsg::QueryRewardStreams {} => to_binary(&query_reward_streams(deps)?),
+        QueryMsg::QueryStreamRewards { address } => {
+            to_binary(&query_stream_rewards(deps, address, env)?)
+        }
+    }
+}
+
+// scans at most `limit` stakers per page, so a filtered page can contain fewer entries
+// even though there are more stakers after next_key
+fn query_stakers(
+    deps: Deps,
+    env: Env,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    filter: Option<StakersFilter>,
+) -> StdResult<StakersResponse> {
+    let limit: usize = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start: Option<Bound> = match start_after {
+        Some(address) => Some(Bound::exclusive(deps.api.addr_validate(&address)?.as_str())),
+        None => None,
+    };
+
+    let page: Vec<(Addr, UserEntry)> = USERS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+
+    let next_key: Option<Addr> = match page.last() {
+        Some((address, _)) if page.len() == limit => Some(address.clone()),
+        _ => None,
+    };
+
+    let filter: StakersFilter = filter.unwrap_or_default();
+    let (state, streams, checkpoints) = advanced_rewards(deps, &env)?;
+
+    let mut stakers: Vec<(Addr, UserEntry)> = vec![];
+    for (address, user) in page {
+        if let Some(min_amount) = filter.min_amount {
+            if user.amount.lt(&min_amount) {
+                continue;
+            }
+        }
+
+        if let Some(has_pending_rewards) = filter.has_pending_rewards {
+            let settled: UserEntry = settled_user(
+                deps,
+                &env,
+                &address,
+                user.clone(),
+                &state,
+                &streams,
+                &checkpoints,
+            )
+            .map_err(|_| StdError::generic_err("could not compute rewards"))?;
+            let pending: bool = !settled.rewards.is_zero()
+                || settled
+                    .stream_rewards
+                    .iter()
+                    .any(|entry| !entry.rewards.is_zero());
+
+            if pending != has_pending_rewards {
+                continue;
+            }
+        }
+
+        if let Some(has_unbonding) = filter.has_unbonding {
+            let unbonding: bool = UNBOND_ENTRIES
+                .prefix(&address)
+                .range(deps.storage, None, None, Order::Ascending)
+                .next()
+                .is_some();
+
+            if unbonding != has_unbonding {
+                continue;
+            }
+        }
+
+        stakers.push((address, user));
+    }
+
+    Ok(StakersResponse { stakers, next_key })
+}
+
+fn query_staker_count(deps: Deps) -> StdResult<u64> {
+    Ok(STAKER_COUNT.may_load(deps.storage)?.unwrap_or_default())
+}
+
+fn query_stake(deps: Deps, address: Addr) -> StdResult<Uint128> {
+    let user: UserEntry = USERS.load(deps.storage, &address)?;
+    let mut total: Uint128 = user.amount;
+    for (_, entry) in unbond_entries(deps, &address)? {
+        total = total.checked_add(entry.unbound_amount)?;
+    }
+
+    Ok(total)
+}
+
+fn query_rewards(deps: Deps, address: Addr, env: Env) -> StdResult<Uint128> {
+    let user: UserEntry = USERS.load(deps.storage, &address)?;
+    let (state, streams, checkpoints) = advanced_rewards(deps, &env)?;
+    let settled: UserEntry =
+        settled_user(deps, &env, &address, user, &state, &streams, &checkpoints)
+            .map_err(|_| StdError::generic_err("could not compute rewards"))?;
+
+    Ok(settled.rewards)
+}
+
+// the state and streams moved forward to the block time of the query, with the checkpoints of
+// the locks that have ended since the last update
+fn advanced_rewards(deps: Deps, env: &Env) -> StdResult<AdvancedRewards> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+    let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default();
+
+    advance_rewards(deps.storage, &state, &config, streams, env)
+        .map_err(|_| StdError::generic_err("could not compute rewards"))
+}
+
+// the user as if settled at the time the state and streams have been advanced to
+fn settled_user(
+    deps: Deps,
+    env: &Env,
+    address: &Addr,
+    user: UserEntry,
+    state: &State,
+    streams: &[RewardStream],
+    checkpoints: &[(u64, BoostCheckpoint)],
+) -> Result<UserEntry, ContractError> {
+    let expired_locks: Vec<(u64, LockEntry)> = expired_lock_entries(deps, env, address)?;
+    let user: UserEntry = settle_expired_locks(deps.storage, user, &expired_locks, checkpoints)?;
+    let rewards: Uint128 = accrued_rewards(
+        user_weight(&user)?,
+        state.reward_per_token_stored,
+        user.user_reward_per_token_paid,
+    )?;
+
+    Ok(UserEntry {
+        rewards: rewards.checked_add(user.rewards)?,
+        user_reward_per_token_paid: state.reward_per_token_stored,
+        stream_rewards: earned_streams(&user, streams)?,
+        ..user
+    })
+}
+
+fn query_unbond_entries(deps: Deps, address: Addr, env: Env) -> StdResult<Vec<UnbondResponse>> {
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+
+    Ok(unbond_entries(deps, &address)?
+        .into_iter()
+        .map(|(id, entry)| UnbondResponse {
+            id,
+            unbound_amount: entry.unbound_amount,
+            expiration_timestamp: entry.expiration_timestamp,
+            expired: entry.expiration_timestamp.le(&current_time),
+            undelegated_amount: if entry.expiration_timestamp.le(&current_time) {
+                Uint128::zero()
+            } else {
+                entry.undelegated_amount
+            },
+        })
+        .collect())
+}
+
+fn query_invariants(deps: Deps, env: Env) -> StdResult<InvariantsResponse> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let state: State = STATE.load(deps.storage)?;
+
+    let mut users_staked: Uint128 = Uint128::zero();
+    let mut users_boost: Uint128 = Uint128::zero();
+    let mut users_with_stake: u64 = 0;
+    for item in USERS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, user): (Addr, UserEntry) = item?;
+        users_staked = users_staked.checked_add(user.amount)?;
+        users_boost = users_boost.checked_add(user.boost)?;
+        if !user.amount.is_zero() {
+            users_with_stake += 1;
+        }
+    }
+
+    // the boost of locks that have ended stopped counting before they are released
+    for item in BOOST_CHECKPOINTS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, checkpoint): (_, BoostCheckpoint) = item?;
+        users_boost = users_boost.checked_sub(checkpoint.unreleased_boost)?;
+    }
+
+    let mut unbond_entries_total: Uint128 = Uint128::zero();
+    for item in UNBOND_ENTRIES.range(deps.storage, None, None, Order::Ascending) {
+        let (_, entry): (_, UnbondEntry) = item?;
+        unbond_entries_total = unbond_entries_total.checked_add(entry.unbound_amount)?;
+    }
+
+    let mut delegations_total: Uint128 = Uint128::zero();
+    for item in DELEGATIONS.range(deps.storage, None, None, Order::Ascending) {
+        let (_, amount): (_, Uint128) = item?;
+        delegations_total = delegations_total.checked_add(amount)?;
+    }
+
+    // while delegating, tranches that haven't matured may still be undelegated by the chain,
+    // so only the matured ones have to be in the contract
+    let required_unbonding: Uint128 = if config.validators.is_empty() && delegations_total.is_zero()
+    {
+        unbond_entries_total
+    } else {
+        let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+        let mut matured: Uint128 = Uint128::zero();
+        for item in UNBOND_ENTRIES.range(deps.storage, None, None, Order::Ascending) {
+            let (_, entry): (_, UnbondEntry) = item?;
+            if entry.expiration_timestamp.le(&current_time) {
+                matured = matured.checked_add(entry.unbound_amount)?;
+            }
+        }
+        matured
+    };
+
+    let staker_count: u64 = STAKER_COUNT.may_load(deps.storage)?.unwrap_or_default();
+    let contract_balance: Uint128 = staking_token_balance(deps, &env, &config);
+
+    let holds: bool = state.staked_balance == users_staked
+        && state.boosted_balance == users_boost
+        && state.unbonding_balance == unbond_entries_total
+        && state.delegated_balance == delegations_total
+        && staker_count == users_with_stake
+        && contract_balance
+            .checked_add(delegations_total)?
+            .ge(&users_staked
+                .checked_add(required_unbonding)?
+                .checked_add(state.reward_reserve)?);
+
+    Ok(InvariantsResponse {
+        staked_balance: state.staked_balance,
+        users_staked,
+        boosted_balance: state.boosted_balance,
+        users_boost,
+        unbonding_balance: state.unbonding_balance,
+        unbond_entries_total,
+        staker_count,
+        users_with_stake,
+        reward_reserve: state.reward_reserve,
+        delegated_balance: state.delegated_balance,
+        delegations_total,
+        contract_balance,
+        holds,
+    })
+}
+
+fn query_locks(deps: Deps, address: Addr, env: Env) -> StdResult<Vec<LockResponse>> {
+    let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+
+    Ok(lock_entries(deps, &address)?
+        .into_iter()
+        .map(|(id, lock)| LockResponse {
+            id,
+            amount: lock.amount,
+            multiplier: lock.multiplier,
+            unlock_timestamp: lock.unlock_timestamp,
+            expired: lock.unlock_timestamp.le(&current_time),
+        })
+        .collect())
+}
+
+fn query_withdraw_address(deps: Deps, address: Addr) -> StdResult<Addr> {
+    withdraw_recipient(deps, &address, None)
+}
+
+// the rewards owed to the stakers are tracked as they are credited, the query only adds the
+// rewards emitted since the last update
+fn query_reward_reserve(deps: Deps, env: Env) -> StdResult<RewardReserveResponse> {
+    let config: Config = CONFIG.load(deps.storage)?;
+    let (state, _, _) = advanced_rewards(deps, &env)?;
+    let outstanding_rewards: Uint128 = state.reward_liabilities;
+
+    let runway: Option<Uint64> = if config.reward_rate.is_zero() {
+        None
+    } else {
+        let unallocated: Uint128 = state.reward_reserve.saturating_sub(outstanding_rewards);
+        Some(Uint64::from(
+            unallocated.checked_div(config.reward_rate)?.u128() as u64,
+        ))
+    };
+
+    Ok(RewardReserveResponse {
+        reward_reserve: state.reward_reserve,
+        outstanding_rewards,
+        runway,
+    })
+}
+
+fn query_delegations(deps: Deps) -> StdResult<Vec<DelegationResponse>> {
+    DELEGATIONS
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (validator, amount) = item?;
+            Ok(DelegationResponse { validator, amount })
+        })
+        .collect()
+}
+
+fn query_exchange_rate(deps: Deps, env: Env) -> StdResult<Decimal> {
+    let (state, streams, checkpoints) = advanced_rewards(deps, &env)?;
+
+    if state.total_shares.is_zero() {
+        return Ok(Decimal::one());
+    }
+
+    let pool: UserEntry = USERS
+        .may_load(deps.storage, &env.contract.address)?
+        .unwrap_or_default();
+
+    // the pending rewards are compounded the next time the pool changes if the reserve covers
+    // them, until then shares are minted and burned for them as well
+    let rewards: Uint128 = settled_user(
+        deps,
+        &env,
+        &env.contract.address,
+        pool.clone(),
+        &state,
+        &streams,
+        &checkpoints,
+    )
+    .map(|settled| settled.rewards)
+    .unwrap_or(pool.rewards);
+    let pool_amount: Uint128 = pool.amount.checked_add(rewards)?;
+
+    Ok(Decimal::from_ratio(pool_amount, state.total_shares))
+}
+
+fn query_total_shares(deps: Deps) -> StdResult<Uint128> {
+    let state: State = STATE.load(deps.storage)?;
+    Ok(state.total_shares)
+}
+
+// bonded stake at the start of the block, the current block if no height is given
+fn query_voting_power_at_height(
+    deps: Deps,
+    env: Env,
+    address: String,
+    height: Option<u64>,
+) -> StdResult<VotingPowerAtHeightResponse> {
+    let address: Addr = deps.api.addr_validate(&address)?;
+    let height: u64 = height.unwrap_or(env.block.height);
+    let power: Uint128 = STAKE_SNAPSHOTS
+        .may_load_at_height(deps.storage, &address, height)?
+        .unwrap_or_default();
+
+    Ok(VotingPowerAtHeightResponse { power, height })
+}
+
+fn query_total_power_at_height(
+    deps: Deps,
+    env: Env,
+    height: Option<u64>,
+) -> StdResult<TotalPowerAtHeightResponse> {
+    let height: u64 = height.unwrap_or(env.block.height);
+    let power: Uint128 = TOTAL_STAKE_SNAPSHOTS
+        .may_load_at_height(deps.storage, height)?
+        .unwrap_or_default();
+
+    Ok(TotalPowerAtHeightResponse { power, height })
+}
+
+fn query_hooks(deps: Deps) -> StdResult<HooksResponse> {
+    let hooks: Vec<Addr> = HOOKS.may_load(deps.storage)?.unwrap_or_default();
+    Ok(HooksResponse {
+        hooks: hooks.into_iter().map(String::from).collect(),
+    })
+}
+
+fn query_reward_streams(deps: Deps) -> StdResult<Vec<RewardStream>> {
+    Ok(REWARD_STREAMS.may_load(deps.storage)?.unwrap_or_default())
+}
+
+fn query_stream_rewards(
+    deps: Deps,
+    address: Addr,
+    env: Env,
+) -> StdResult<Vec<StreamRewardResponse>> {
+    let user: UserEntry = USERS.load(deps.storage, &address)?;
+    let (state, streams, checkpoints) = advanced_rewards(deps, &env)?;
+    let settled: UserEntry =
+        settled_user(deps, &env, &address, user, &state, &streams, &checkpoints)
+            .map_err(|_| StdError::generic_err("could not compute stream rewards"))?;
+
+    Ok(streams
+        .into_iter()
+        .zip(settled.stream_rewards)
+        .map(|(stream, entry)| StreamRewardResponse {
+            denom: stream.denom,
+            rewards: entry.rewards,
+        })
+        .collect())
+}
+
+fn query_config(deps: Deps) -> StdResult<Config> {
+    let config: Config = CONFIG.load(deps.storage)?;
+
+    Ok(config)
+}
+
+fn query_ownership_proposal(deps: Deps) -> StdResult<Option<Addr>> {
+    OWNERSHIP_PROPOSAL.may_load(deps.storage)
+}
+
+fn query_state(deps: Deps, env: Env) -> StdResult<State> {
+    let mut state: State = STATE.load(deps.storage)?;
+
+    // report the budget that is left at the queried time rather than at the last update
+    let (advanced_state, _, _) = advanced_rewards(deps, &env)?;
+    state.reward_budget = advanced_state.reward_budget;
+
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations::{LegacyState, LegacyUnbondEntry, LEGACY_STATE, LEGACY_UNBOND_ENTRIES};
+    use anyhow::Result as AnyResult;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        attr, coin, coins, from_binary, from_slice, AllValidatorsResponse, Api, BankMsg, Binary,
+        BlockInfo, BondedDenomResponse, ContractInfo, CosmosMsg, CustomQuery, Querier,
+        StakingQuery, Timestamp, TransactionInfo, Validator, ValidatorResponse,
+    };
+    use cw_multi_test::{
+        AppBuilder, AppResponse, ContractWrapper, CosmosRouter, Executor, Module, Staking,
+        StakingSudo,
+    };
+    use cw_storage_plus::Map;
+    use schemars::JsonSchema;
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    #[test]
+    fn proper_initialization() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+
+        let env = mock_env();
+
+        // we can just call .unwrap() to assert this was a success
+        let res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+        assert_eq!(0, res.messages.len());
+
+        // it worked, let's query the config
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+        assert_eq!(
+            Config {
+                owner: Addr::unchecked("creator"),
+                chief_pausing_officer: Addr::unchecked("creator"),
+                denom: "nanomobx".to_string(),
+                reward_rate: Uint128::zero(),
+                paused: false,
+                unbonding_period: Uint64::zero(),
+                staking_token: None,
+                lock_tiers: vec![],
+                early_unbond_penalty: Decimal::zero(),
+                penalty_destination: PenaltyDestination::default(),
+                validators: vec![],
+                chain_unbonding_period: Uint64::zero(),
+                share_token: None,
+                transfers_enabled: false,
+                reward_schedule: vec![],
+            },
+            value
+        );
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+        assert_eq!(
+            State {
+                reward_per_token_stored: Decimal256::zero(),
+                last_update_time: env.block.time,
+                staked_balance: Uint128::zero(),
+                period_finish: None,
+                reward_budget: Uint128::zero(),
+                boosted_balance: Uint128::zero(),
+                unbonding_balance: Uint128::zero(),
+                reward_reserve: Uint128::zero(),
+                delegated_balance: Uint128::zero(),
+                total_shares: Uint128::zero(),
+                emission_remainder: Uint128::zero(),
+                reward_dust: Uint256::zero(),
+                reward_liabilities: Uint128::zero(),
+            },
+            value
+        );
+    }
+
+    #[test]
+    fn migrate_from_legacy_storage() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+
+        // storage as written by the first deployment, without any contract version
+        CONFIG
+            .save(
+                deps.as_mut().storage,
+                &Config {
+                    owner: Addr::unchecked("creator"),
+                    chief_pausing_officer: Addr::unchecked("creator"),
+                    denom: "nanomobx".to_string(),
+                    reward_rate: Uint128::from(1u128),
+                    paused: false,
+                    unbonding_period: Uint64::from(1u64),
+                    staking_token: None,
+                    lock_tiers: vec![],
+                    early_unbond_penalty: Decimal::zero(),
+                    penalty_destination: PenaltyDestination::default(),
+                    validators: vec![],
+                    chain_unbonding_period: Uint64::zero(),
+                    share_token: None,
+                    transfers_enabled: false,
+                    reward_schedule: vec![],
+                },
+            )
+            .unwrap();
+        LEGACY_STATE
+            .save(
+                deps.as_mut().storage,
+                &LegacyState {
+                    reward_per_token_stored: Uint128::from(5u128),
+                    last_update_time: env.block.time,
+                    staked_balance: Uint128::from(30u128),
+                },
+            )
+            .unwrap();
+        for (address, amount, is_valid) in [("anyone", 10u128, true), ("another", 20u128, false)] {
+            LEGACY_UNBOND_ENTRIES
+                .save(
+                    deps.as_mut().storage,
+                    &Addr::unchecked(address),
+                    &LegacyUnbondEntry {
+                        unbound_amount: Uint128::from(amount),
+                        expiration_timestamp: Uint64::from(env.block.time.nanos()),
+                        is_valid,
+                    },
+                )
+                .unwrap();
+        }
+
+        let res = migrate(deps.as_mut(), env.clone(), MigrateMsg::Migrate {}).unwrap();
+
+        assert_eq!(res.attributes[1], attr("from_version", "0.1.0"));
+        assert_eq!(
+            cw2::get_contract_version(deps.as_ref().storage).unwrap(),
+            ContractVersion {
+                contract: CONTRACT_NAME.to_string(),
+                version: CONTRACT_VERSION.to_string(),
+            }
+        );
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        // the pending unbond moves out of the staked balance
+        assert_eq!(
+            Decimal256::from_atomics(5u128, 9).unwrap(),
+            value.reward_per_token_stored
+        );
+        assert_eq!(Uint128::from(20u128), value.staked_balance);
+        assert_eq!(Uint128::from(10u128), value.unbonding_balance);
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::QueryTotalPowerAtHeight {
+                height: Some(env.block.height + 1),
+            },
+        )
+        .unwrap();
+        let value: TotalPowerAtHeightResponse = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(20u128), value.power);
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(Uint128::from(10u128), value[0].unbound_amount);
+
+        // already withdrawn entries are dropped
+        let res = query(
+            deps.as_ref(),
+            env,
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("another"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert!(value.is_empty());
+    }
+
+    #[test]
+    fn migrate_rescales_reward_per_token() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // 0.2.0 stored the accumulators as integers scaled by 10^9, "2000000000" reads as 2e9
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.2.0").unwrap();
+        let mut state: State = STATE.load(deps.as_ref().storage).unwrap();
+        state.reward_per_token_stored = Decimal256::from_ratio(2_000_000_000u128, 1u128);
+        STATE.save(deps.as_mut().storage, &state).unwrap();
+        let mut user: UserEntry = USERS
+            .load(deps.as_ref().storage, &Addr::unchecked("anyone"))
+            .unwrap();
+        user.user_reward_per_token_paid = Decimal256::from_ratio(1_000_000_000u128, 1u128);
+        USERS
+            .save(deps.as_mut().storage, &Addr::unchecked("anyone"), &user)
+            .unwrap();
+
+        let _res = migrate(deps.as_mut(), env.clone(), MigrateMsg::Migrate {}).unwrap();
+
+        // the rewards owed to the stakers are summed up once
+        let state: State = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::from(10u128), state.reward_liabilities);
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(1);
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+    }
+
+    #[test]
+    fn migrate_keeps_drained_reserves() {
+        let mut deps = mock_dependencies_with_balance(&[coin(50, "nanomobx"), coin(30, "uatom")]);
+        let env = mock_env();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardStream {
+                denom: Denom::Native("uatom".to_string()),
+                reward_rate: Uint128::from(1u128),
+            },
+        )
+        .unwrap();
+
+        // the coins on top of the stake can be swept, they don't become rewards on an upgrade
+        let _res = migrate(deps.as_mut(), env, MigrateMsg::Migrate {}).unwrap();
+
+        let state: State = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::zero(), state.reward_reserve);
+
+        let streams: Vec<RewardStream> = REWARD_STREAMS.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::zero(), streams[0].reward_reserve);
+    }
+
+    #[test]
+    fn rewards_accrue_with_sub_second_precision() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(10u128),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // half a second of emission used to be dropped by this update
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_nanos(500_000_000);
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("bob", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        new_env.block.time = env.block.time.plus_nanos(1_500_000_000);
+
+        for (address, rewards) in [("alice", 10u128), ("bob", 5u128)] {
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryRewards {
+                    address: Addr::unchecked(address),
+                },
+            )
+            .unwrap();
+            let value: Uint128 = from_binary(&res).unwrap();
+
+            assert_eq!(Uint128::from(rewards), value);
+        }
+    }
+
+    #[test]
+    fn migrate_refuses_downgrade_and_other_contracts() {
+        let mut deps = mock_dependencies();
+
+        set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg::Migrate {}).unwrap_err();
+
+        match err {
+            ContractError::CannotDowngrade { .. } => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        set_contract_version(deps.as_mut().storage, "crates.io:cw20-base", "0.1.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg::Migrate {}).unwrap_err();
+
+        match err {
+            ContractError::InvalidContractName { .. } => {}
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn update_config() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let old_config: Config = from_binary(&res).unwrap();
+
+        let msgs = vec![
+            ExecuteMsg::SetChiefPausingOfficer {
+                address: "CPO".to_string(),
+            },
+            ExecuteMsg::SetRewardRate {
+                reward_rate: Uint128::from(1u128),
+            },
+            ExecuteMsg::SetUnbondingPeriod {
+                unbonding_period: Uint64::from(1u64),
+            },
+        ];
+        for msg in msgs {
+            let _res = execute(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        }
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let current_config: Config = from_binary(&res).unwrap();
+        assert_eq!(
+            Config {
+                owner: Addr::unchecked("creator"),
+                chief_pausing_officer: Addr::unchecked("CPO"),
+                denom: "nanomobx".to_string(),
+                reward_rate: Uint128::from(1u128),
+                paused: false,
+                unbonding_period: Uint64::from(1u64),
+                staking_token: None,
+                lock_tiers: vec![],
+                early_unbond_penalty: Decimal::zero(),
+                penalty_destination: PenaltyDestination::default(),
+                validators: vec![],
+                chain_unbonding_period: Uint64::zero(),
+                share_token: None,
+                transfers_enabled: false,
+                reward_schedule: vec![],
+            },
+            current_config
+        );
+        assert_eq!(old_config.denom, current_config.denom);
+    }
+
+    #[test]
+    fn cpo_should_only_update_cpo_and_paused() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        // the owner hires a new CPO
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::SetChiefPausingOfficer {
+                address: "cpo".to_string(),
+            },
+        )
+        .unwrap();
+
+        // the CPO tries to take over but fails
+        let cpo_info = mock_info("cpo", &[]);
+        let malicious_msgs = vec![
+            ExecuteMsg::ProposeNewOwner {
+                owner: "cpo".to_string(),
+            },
+            ExecuteMsg::SetRewardRate {
+                reward_rate: Uint128::from(1_000_000_000u128),
+            },
+            ExecuteMsg::SetUnbondingPeriod {
+                unbonding_period: Uint64::zero(),
+            },
+        ];
+        for msg in malicious_msgs {
+            let err = execute(deps.as_mut(), env.clone(), cpo_info.clone(), msg).unwrap_err();
+
+            match err {
+                ContractError::Unauthorized {} => {}
+                e => panic!("unexpected error: {}", e),
+            }
+        }
+
+        // but can pause the contract and hand over the role
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            cpo_info.clone(),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap();
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            cpo_info,
+            ExecuteMsg::SetChiefPausingOfficer {
+                address: "cpo2".to_string(),
+            },
+        )
+        .unwrap();
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let current_config: Config = from_binary(&res).unwrap();
+
+        assert!(current_config.paused);
+        assert_eq!(Addr::unchecked("creator"), current_config.owner);
+        assert_eq!(
+            Addr::unchecked("cpo2"),
+            current_config.chief_pausing_officer
+        );
+        assert_eq!(Uint128::zero(), current_config.reward_rate);
+    }
+
+    #[test]
+    fn two_step_ownership_transfer() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let creator_info = mock_info("creator", &[]);
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), creator_info.clone(), msg).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("new_owner", &[]),
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::NoOwnershipProposal {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        // a proposal to a wrong address can be cancelled
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            creator_info.clone(),
+            ExecuteMsg::ProposeNewOwner {
+                owner: "typo".to_string(),
+            },
+        )
+        .unwrap();
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            creator_info.clone(),
+            ExecuteMsg::CancelOwnershipProposal {},
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::QueryOwnershipProposal {},
+        )
+        .unwrap();
+        let value: Option<Addr> = from_binary(&res).unwrap();
+
+        assert_eq!(None, value);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            creator_info.clone(),
+            ExecuteMsg::ProposeNewOwner {
+                owner: "new_owner".to_string(),
+            },
+        )
+        .unwrap();
+
+        // the owner doesn't change until the proposed owner accepts
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+
+        assert_eq!(Addr::unchecked("creator"), value.owner);
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("someone_else", &[]),
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::Unauthorized {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("new_owner", &[]),
+            ExecuteMsg::AcceptOwnership {},
+        )
+        .unwrap();
+
+        assert_eq!(res.attributes[3], attr("owner", "new_owner"));
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+
+        assert_eq!(Addr::unchecked("new_owner"), value.owner);
+
+        // the previous owner lost its rights
+        let err = execute(
+            deps.as_mut(),
+            env,
+            creator_info,
+            ExecuteMsg::SetRewardRate {
+                reward_rate: Uint128::from(1u128),
+            },
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::Unauthorized {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn add_stake() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), env.clone(), info, add_stake_msg).unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::QueryStake {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+    }
+
+    #[test]
+    fn cw20_stake_and_remove_stake() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: Some("staking_token".to_string()),
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        // native coins are not accepted when staking a CW20 token
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::InvalidToken {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let receive_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "anyone".to_string(),
+            amount: Uint128::from(10u128),
+            msg: to_binary(&ReceiveMsg::Stake {
+                recipient: None,
+                lock_duration: None,
+            })
+            .unwrap(),
+        });
+
+        // neither are other CW20 tokens
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("other_token", &[]),
+            receive_msg.clone(),
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::InvalidToken {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("staking_token", &[]),
+            receive_msg,
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::QueryStake {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+
+        let info = mock_info("anyone", &[]);
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
+        let res = execute(
+            deps.as_mut(),
+            env,
+            info,
+            ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "staking_token".into(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "anyone".into(),
+                    amount: Uint128::from(10u128),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn unbond_and_remove_stake() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.height += 3;
+
+        let unbond_msg = ExecuteMsg::Unbond {
+            amount: Uint128::from(10u128),
+        };
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            unbond_msg,
+        );
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(
+            Uint64::from(new_env.block.time.nanos()),
+            value[0].expiration_timestamp
+        );
+        assert!(value[0].expired);
+
+        let remove_stake_msg = ExecuteMsg::RemoveStake {
+            amount: None,
+            recipient: None,
+        };
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            remove_stake_msg,
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert!(value.is_empty());
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryStake {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(0u128), value);
+    }
+
+    #[test]
+    fn unbond_twice() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::zero(),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+
+        let mut new_env = mock_env();
+        // new_env.block.height += 3;
+        new_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 3 * 1_000_000_000);
+
+        let unbond_msg = ExecuteMsg::Unbond {
+            amount: Uint128::from(10u128),
+        };
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            unbond_msg,
+        );
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(
+            Uint64::from(new_env.block.time.nanos()),
+            value[0].expiration_timestamp
+        );
+        assert!(value[0].expired);
+
+        let remove_stake_msg = ExecuteMsg::RemoveStake {
+            amount: None,
+            recipient: None,
+        };
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            remove_stake_msg,
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert!(value.is_empty());
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryStake {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(0u128), value);
+
+        let second_unbond_msg = ExecuteMsg::Unbond {
+            amount: Uint128::from(10u128),
+        };
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            second_unbond_msg,
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::NoRecordAvailable {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn unbond_period() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(300u64),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.height += 3;
+
+        let unbond_msg = ExecuteMsg::Unbond {
+            amount: Uint128::from(10u128),
+        };
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            unbond_msg,
+        );
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        let billion: Uint64 = Uint64::from(10u64.pow(9));
+        let current_time: Uint64 = Uint64::from(env.block.time.nanos());
+        let expiration_timestamp: Uint64 = current_time
+            .checked_add(Uint64::from(300u64).checked_mul(billion).unwrap())
+            .unwrap();
+        assert_eq!(1, value.len());
+        assert_eq!(expiration_timestamp, value[0].expiration_timestamp);
+        assert!(!value[0].expired);
+
+        let remove_stake_msg = ExecuteMsg::RemoveStake {
+            amount: None,
+            recipient: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            remove_stake_msg,
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::BondedStake {} => {}
+            e => panic!("unexpecter error: {}", e),
+        }
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(expiration_timestamp.clone(), value[0].expiration_timestamp);
+        assert!(!value[0].expired);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryStake {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+
+        let mut newest_env = mock_env();
+        // new_env.block.height += 3;
+        newest_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 300 * 1_000_000_000);
+
+        let remove_stake_msg = ExecuteMsg::RemoveStake {
+            amount: None,
+            recipient: None,
+        };
+        let _res = execute(
+            deps.as_mut(),
+            newest_env.clone(),
+            mock_info("anyone", &[]),
+            remove_stake_msg,
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            newest_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert!(value.is_empty());
+
+        let res = query(
+            deps.as_ref(),
+            newest_env.clone(),
+            QueryMsg::QueryStake {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(0u128), value);
+    }
+
+    #[test]
+    fn unbond_tranches_mature_independently() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(300u64),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let info = mock_info("anyone", &coins(11, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // unbond 10 now and 1 more after 200 seconds
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
+
+        let mut later_env = mock_env();
+        later_env.block.time = env.block.time.plus_seconds(200);
+        let _res = execute(
+            deps.as_mut(),
+            later_env,
+            mock_info("anyone", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(1u128),
+            },
+        )
+        .unwrap();
+
+        // only the first tranche has matured after 300 seconds
+        let mut matured_env = mock_env();
+        matured_env.block.time = env.block.time.plus_seconds(300);
+
+        let res = query(
+            deps.as_ref(),
+            matured_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(2, value.len());
+        assert_eq!(Uint128::from(10u128), value[0].unbound_amount);
+        assert!(value[0].expired);
+        assert_eq!(Uint128::from(1u128), value[1].unbound_amount);
+        assert!(!value[1].expired);
+
+        let res = execute(
+            deps.as_mut(),
+            matured_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".into(),
+                amount: coins(10, "nanomobx"),
+            })
+        );
+
+        let res = query(
+            deps.as_ref(),
+            matured_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(Uint128::from(1u128), value[0].unbound_amount);
+
+        let err = execute(
+            deps.as_mut(),
+            matured_env,
+            mock_info("anyone", &[]),
+            ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
+            },
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::BondedStake {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn partially_remove_expired_stake() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(300u64),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+        let info = mock_info("anyone", &coins(15, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // two tranches of 5 and 10 that have both matured
+        for amount in [5u128, 10u128] {
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info("anyone", &[]),
+                ExecuteMsg::Unbond {
+                    amount: Uint128::from(amount),
+                },
+            )
+            .unwrap();
+        }
+
+        let mut matured_env = mock_env();
+        matured_env.block.time = env.block.time.plus_seconds(300);
+
+        let err = execute(
+            deps.as_mut(),
+            matured_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::RemoveStake {
+                amount: Some(Uint128::from(16u128)),
+                recipient: None,
+            },
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::NotEnoughExpiredStakeToRemove {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            matured_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::RemoveStake {
+                amount: Some(Uint128::from(7u128)),
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".into(),
+                amount: coins(7, "nanomobx"),
+            })
+        );
+
+        // the first tranche is gone and the second one keeps the remainder
+        let res = query(
+            deps.as_ref(),
+            matured_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(Uint128::from(8u128), value[0].unbound_amount);
+
+        let res = query(deps.as_ref(), matured_env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        // unbonding stake no longer counts as staked
+        assert_eq!(Uint128::zero(), value.staked_balance);
+        assert_eq!(Uint128::from(8u128), value.unbonding_balance);
+
+        let res = execute(
+            deps.as_mut(),
+            matured_env,
+            mock_info("anyone", &[]),
+            ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".into(),
+                amount: coins(8, "nanomobx"),
+            })
+        );
+    }
+
+    fn env_at_height(height: u64) -> Env {
+        let time = Timestamp::from_seconds((5u64 * height) + 1u64);
+
+        Env {
+            block: BlockInfo {
+                height,
+                time,
+                chain_id: Default::default(),
+            },
+            contract: ContractInfo {
+                address: Addr::unchecked(MOCK_CONTRACT_ADDR),
+            },
+            transaction: { Some(TransactionInfo { index: 0 }) },
+        }
+    }
+
+    #[test]
+    fn check_result_claim_failure_due_to_high_reward_rate() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1_000_000_000u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        // create the contract
+        instantiate(
+            deps.as_mut(),
+            env_at_height(1),
+            mock_info("creator", &coins(1000, "nanomobx")),
+            msg,
+        )
+        .unwrap();
+
+        // add a series of stakes
+        execute(
+            deps.as_mut(),
+            env_at_height(2),
+            mock_info("user1", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env_at_height(2),
+            mock_info("user2", &coins(200, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env_at_height(2),
+            mock_info("user3", &coins(20000, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            USERS.may_load(deps.as_ref().storage, &Addr::unchecked("user1")),
+            Ok(Some(UserEntry {
+                amount: Uint128::from(10u128),
+                rewards: Uint128::zero(),
+                user_reward_per_token_paid: Decimal256::zero(),
+                stream_rewards: vec![],
+                auto_compound: false,
+                boost: Uint128::zero(),
+            }))
+        );
+
+        assert_eq!(
+            USERS.may_load(deps.as_ref().storage, &Addr::unchecked("user2")),
+            Ok(Some(UserEntry {
+                amount: Uint128::from(200u128),
+                rewards: Uint128::zero(),
+                user_reward_per_token_paid: Decimal256::zero(),
+                stream_rewards: vec![],
+                auto_compound: false,
+                boost: Uint128::zero(),
+            }))
+        );
+
+        assert_eq!(
+            USERS.may_load(deps.as_ref().storage, &Addr::unchecked("user3")),
+            Ok(Some(UserEntry {
+                amount: Uint128::from(20000u128),
+                rewards: Uint128::zero(),
+                user_reward_per_token_paid: Decimal256::zero(),
+                stream_rewards: vec![],
+                auto_compound: false,
+                boost: Uint128::zero(),
+            }))
+        );
+
+        // trigger calculation of rewards - will all fail because the reward rate is so high
+        assert_eq!(
+            execute(
+                deps.as_mut(),
+                env_at_height(12),
+                mock_info("user1", &[]),
+                ExecuteMsg::ClaimRewards {
+                    recipient: None,
+                    amount: None,
+                    allow_partial: false,
+                },
+            ),
+            Err(ContractError::NoFundsAvailable {})
+        );
+        assert_eq!(
+            execute(
+                deps.as_mut(),
+                env_at_height(12),
+                mock_info("user2", &[]),
+                ExecuteMsg::ClaimRewards {
+                    recipient: None,
+                    amount: None,
+                    allow_partial: false,
+                },
+            ),
+            Err(ContractError::NoFundsAvailable {})
+        );
+        assert_eq!(
+            execute(
+                deps.as_mut(),
+                env_at_height(12),
+                mock_info("user3", &[]),
+                ExecuteMsg::ClaimRewards {
+                    recipient: None,
+                    amount: None,
+                    allow_partial: false,
+                },
+            ),
+            Err(ContractError::NoFundsAvailable {})
+        );
+    }
+
+    #[test]
+    fn claim_rewards() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(200, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+
+        let other_info = mock_info("another", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            other_info.clone(),
+            add_stake_msg,
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.height += 4;
+        new_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 4 * 1_000_000_000);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(2u128), value);
+
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), new_env.clone(), info.clone(), add_stake_msg).unwrap();
+
+        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        assert_eq!(value.last_update_time, new_env.block.time);
+        assert_eq!(value.staked_balance, Uint128::from(30u128));
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(2u128), value);
+
+        let claim_msg = ExecuteMsg::ClaimRewards {
+            recipient: None,
+            amount: None,
+            allow_partial: false,
+        };
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            claim_msg,
+        );
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::zero(), value);
+    }
+
+    #[test]
+    fn claim_rewards_right_after_stake() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(200, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.height += 4;
+        new_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 4 * 1_000_000_000);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(4u128), value);
+
+        let claim_msg = ExecuteMsg::ClaimRewards {
+            recipient: None,
+            amount: None,
+            allow_partial: false,
+        };
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            claim_msg,
+        )
+        .unwrap();
+
+        assert_eq!(res.attributes.len(), 10);
+        assert_eq!(res.attributes[0], attr("action", "claim"));
+        assert_eq!(res.attributes[2], attr("paid", "4"));
+        assert_eq!(res.attributes[3], attr("outstanding", "0"));
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".into(),
+                amount: coins(4, "nanomobx"),
+            })
+        );
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::zero(), value);
+    }
+
+    #[test]
+    fn compound_rewards_into_stake() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(200, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(4);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Compound {},
+        )
+        .unwrap();
+
+        assert_eq!(res.attributes[2], attr("amount", "4"));
+        assert!(res.messages.is_empty());
+
+        let user: UserEntry = USERS
+            .load(deps.as_ref().storage, &Addr::unchecked("anyone"))
+            .unwrap();
+
+        assert_eq!(Uint128::from(14u128), user.amount);
+        assert_eq!(Uint128::zero(), user.rewards);
+
+        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(14u128), value.staked_balance);
+
+        // with auto compounding the rewards are restaked on the next interaction
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            info.clone(),
+            ExecuteMsg::SetAutoCompound { enabled: true },
+        )
+        .unwrap();
+
+        let mut newest_env = mock_env();
+        newest_env.block.time = env.block.time.plus_seconds(11);
+
+        let _res = execute(
+            deps.as_mut(),
+            newest_env.clone(),
+            info,
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let user: UserEntry = USERS
+            .load(deps.as_ref().storage, &Addr::unchecked("anyone"))
+            .unwrap();
+
+        assert_eq!(Uint128::from(31u128), user.amount);
+        assert_eq!(Uint128::zero(), user.rewards);
+        assert!(user.auto_compound);
+    }
+
+    #[test]
+    fn stake_for_beneficiary_and_pay_out_to_recipient() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(200, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        // the custodian funds the stake, the beneficiary owns it
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("custodian", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: Some("beneficiary".to_string()),
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        assert!(USERS
+            .may_load(deps.as_ref().storage, &Addr::unchecked("custodian"))
+            .unwrap()
+            .is_none());
+
+        let info = mock_info("beneficiary", &[]);
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(4);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            info.clone(),
+            ExecuteMsg::ClaimRewards {
+                recipient: Some("vault".to_string()),
+                amount: None,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "vault".to_string(),
+                amount: coins(4, "nanomobx"),
+            })
+        );
+
+        // a default withdraw address is used when no recipient is given
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            info.clone(),
+            ExecuteMsg::SetWithdrawAddress {
+                address: Some("cold".to_string()),
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryWithdrawAddress {
+                address: Addr::unchecked("beneficiary"),
+            },
+        )
+        .unwrap();
+        let value: Addr = from_binary(&res).unwrap();
+
+        assert_eq!(Addr::unchecked("cold"), value);
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            info.clone(),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
+
+        let mut newest_env = mock_env();
+        newest_env.block.time = new_env.block.time.plus_seconds(2);
+
+        let res = execute(
+            deps.as_mut(),
+            newest_env,
+            info.clone(),
+            ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "cold".to_string(),
+                amount: coins(10, "nanomobx"),
+            })
+        );
+
+        // resetting points payouts back at the staker
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            info,
+            ExecuteMsg::SetWithdrawAddress { address: None },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryWithdrawAddress {
+                address: Addr::unchecked("beneficiary"),
+            },
+        )
+        .unwrap();
+        let value: Addr = from_binary(&res).unwrap();
+
+        assert_eq!(Addr::unchecked("beneficiary"), value);
+    }
+
+    #[test]
+    fn locked_stake_earns_boosted_rewards() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(3u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let thirty_days: Uint64 = Uint64::from(30 * 24 * 60 * 60u64);
+        let lock_tiers: Vec<LockTier> = vec![LockTier {
+            duration: thirty_days,
+            multiplier: Decimal::percent(200),
+        }];
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::SetLockTiers {
+                lock_tiers: lock_tiers.clone(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::Unauthorized {}, err);
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetLockTiers {
+                lock_tiers: vec![LockTier {
+                    duration: thirty_days,
+                    multiplier: Decimal::percent(50),
+                }],
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidLockTier {}, err);
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetLockTiers { lock_tiers },
+        )
+        .unwrap();
+
+        // there were no tiers before, an empty attribute value would be rejected by the chain
+        assert!(res
+            .attributes
+            .contains(&attr("previous_lock_tiers", "none")));
+        assert!(res.attributes.contains(&attr("lock_tiers", "2592000:2")));
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("locker", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: Some(Uint64::from(1u64)),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::UnknownLockTier {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("locker", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: Some(thirty_days),
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // the locked stake weighs twice as much, so it gets 2 of every 3 rewards
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(10);
+
+        for (address, expected) in [("locker", 20u128), ("anyone", 10u128)] {
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryRewards {
+                    address: Addr::unchecked(address),
+                },
+            )
+            .unwrap();
+            let value: Uint128 = from_binary(&res).unwrap();
+
+            assert_eq!(Uint128::from(expected), value);
+        }
+
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("locker", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(1u128),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::LockedStake {}, err);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryLocks {
+                address: Addr::unchecked("locker"),
+            },
+        )
+        .unwrap();
+        let value: Vec<LockResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(1, value.len());
+        assert_eq!(Uint128::from(10u128), value[0].amount);
+        assert_eq!(Decimal::percent(200), value[0].multiplier);
+        assert!(!value[0].expired);
+
+        // once the lock ends the stake can be unbonded and the boost is gone
+        let mut unlocked_env = mock_env();
+        unlocked_env.block.time = env.block.time.plus_seconds(thirty_days.u64());
+
+        let _res = execute(
+            deps.as_mut(),
+            unlocked_env.clone(),
+            mock_info("locker", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            unlocked_env.clone(),
+            QueryMsg::QueryLocks {
+                address: Addr::unchecked("locker"),
+            },
+        )
+        .unwrap();
+        let value: Vec<LockResponse> = from_binary(&res).unwrap();
+
+        assert!(value.is_empty());
+
+        let res = query(deps.as_ref(), unlocked_env, QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::zero(), value.boosted_balance);
+    }
+
+    #[test]
+    fn lock_boost_ends_at_unlock_time() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(10u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetLockTiers {
+                lock_tiers: vec![LockTier {
+                    duration: Uint64::from(10u64),
+                    multiplier: Decimal::percent(300),
+                }],
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("locker", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: Some(Uint64::from(10u64)),
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // 75 and 25 of the first 100 rewards while the lock runs, an even split of the rest
+        // although the locker never released the lock
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(1000);
+
+        let expected_rewards = [("locker", 5025u128), ("anyone", 4975u128)];
+        for (address, expected) in expected_rewards {
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryRewards {
+                    address: Addr::unchecked(address),
+                },
+            )
+            .unwrap();
+            let value: Uint128 = from_binary(&res).unwrap();
+
+            assert_eq!(Uint128::from(expected), value);
+        }
+
+        // the next update stores the checkpoint of the lock, without releasing it
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("other", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryInvariants {}).unwrap();
+        let value: InvariantsResponse = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::zero(), value.boosted_balance);
+        assert_eq!(Uint128::zero(), value.users_boost);
+
+        for (address, expected) in expected_rewards {
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryRewards {
+                    address: Addr::unchecked(address),
+                },
+            )
+            .unwrap();
+            let value: Uint128 = from_binary(&res).unwrap();
+
+            assert_eq!(Uint128::from(expected), value);
+        }
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("locker", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("locker"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(5025u128), value);
+    }
+
+    #[test]
+    fn remove_stake_early_with_penalty_to_treasury() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetEarlyUnbondPenalty {
+                penalty: Decimal::percent(101),
+                destination: PenaltyDestination::Burn {},
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidPenalty {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetEarlyUnbondPenalty {
+                penalty: Decimal::percent(10),
+                destination: PenaltyDestination::Treasury {
+                    address: Addr::unchecked("treasury"),
+                },
+            },
+        )
+        .unwrap();
+
+        let info = mock_info("anyone", &coins(100, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(50u128),
+            },
+        )
+        .unwrap();
+
+        // halfway through the unbonding period the tranche pays half the penalty,
+        // the bonded stake pays all of it
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(50);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::RemoveStakeEarly {
+                amount: Uint128::from(80u128),
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res.attributes[3], attr("penalty", "5"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(75, "nanomobx"),
+            })
+        );
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "treasury".to_string(),
+                amount: coins(5, "nanomobx"),
+            })
+        );
+
+        let user: UserEntry = USERS
+            .load(deps.as_ref().storage, &Addr::unchecked("anyone"))
+            .unwrap();
+        assert_eq!(Uint128::from(20u128), user.amount);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+        assert!(value.is_empty());
+
+        let res = query(deps.as_ref(), new_env, QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(20u128), value.staked_balance);
+    }
+
+    #[test]
+    fn remove_stake_early_redistributes_penalty() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetEarlyUnbondPenalty {
+                penalty: Decimal::percent(10),
+                destination: PenaltyDestination::Redistribute {},
+            },
+        )
+        .unwrap();
+
+        for address in ["leaver", "stayer"] {
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(address, &coins(100, "nanomobx")),
+                ExecuteMsg::AddStake {
+                    recipient: None,
+                    lock_duration: None,
+                },
+            )
+            .unwrap();
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("leaver", &[]),
+            ExecuteMsg::RemoveStakeEarly {
+                amount: Uint128::from(100u128),
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(1, res.messages.len());
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(1);
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("stayer"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+    }
+
+    fn mock_delegation(validator: &str, amount: u128, rewards: u128) -> FullDelegation {
+        FullDelegation {
+            delegator: Addr::unchecked(MOCK_CONTRACT_ADDR),
+            validator: validator.to_string(),
+            amount: Coin::new(amount, "nanomobx"),
+            can_redelegate: Coin::new(amount, "nanomobx"),
+            accumulated_rewards: coins(rewards, "nanomobx"),
+        }
+    }
+
+    #[test]
+    fn delegate_stake_and_harvest_rewards() {
+        let mut deps = mock_dependencies();
+
+        let validators: Vec<Validator> = ["val1", "val2"]
+            .iter()
+            .map(|address| Validator {
+                address: address.to_string(),
+                commission: Decimal::percent(5),
+                max_commission: Decimal::percent(10),
+                max_change_rate: Decimal::percent(1),
+            })
+            .collect();
+        deps.querier.update_staking("nanomobx", &validators, &[]);
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetValidators {
+                validators: vec!["val1".to_string(), "unknown".to_string()],
+                chain_unbonding_period: Uint64::from(100u64),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidValidators {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetValidators {
+                validators: vec!["val1".to_string(), "val2".to_string()],
+                chain_unbonding_period: Uint64::from(100u64),
+            },
+        )
+        .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                CosmosMsg::Staking(StakingMsg::Delegate {
+                    validator: "val1".to_string(),
+                    amount: Coin::new(50, "nanomobx"),
+                }),
+                CosmosMsg::Staking(StakingMsg::Delegate {
+                    validator: "val2".to_string(),
+                    amount: Coin::new(50, "nanomobx"),
+                }),
+            ],
+            res.messages
+                .into_iter()
+                .map(|sub_msg| sub_msg.msg)
+                .collect::<Vec<CosmosMsg>>()
+        );
+
+        // the native staking rewards are distributed next to the reward_rate emission
+        deps.querier.update_staking(
+            "nanomobx",
+            &validators,
+            &[
+                mock_delegation("val1", 50, 20),
+                mock_delegation("val2", 50, 10),
+            ],
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Harvest {},
+        )
+        .unwrap();
+
+        assert_eq!(2, res.messages.len());
+        assert_eq!(
+            CosmosMsg::Distribution(DistributionMsg::WithdrawDelegatorReward {
+                validator: "val1".to_string(),
+            }),
+            res.messages[0].msg
+        );
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(1);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("alice"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(30u128), value);
+
+        deps.querier.update_staking(
+            "nanomobx",
+            &validators,
+            &[
+                mock_delegation("val1", 50, 0),
+                mock_delegation("val2", 50, 0),
+            ],
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(60u128),
+            },
+        )
+        .unwrap();
+
+        assert_eq!(2, res.messages.len());
+        assert_eq!(
+            CosmosMsg::Staking(StakingMsg::Undelegate {
+                validator: "val2".to_string(),
+                amount: Coin::new(10, "nanomobx"),
+            }),
+            res.messages[1].msg
+        );
+
+        let res = query(deps.as_ref(), new_env, QueryMsg::QueryDelegations {}).unwrap();
+        let value: Vec<DelegationResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(
+            vec![DelegationResponse {
+                validator: "val2".to_string(),
+                amount: Uint128::from(40u128),
+            }],
+            value
+        );
+    }
+
+    #[test]
+    fn set_validators_follows_the_chain() {
+        let mut deps = mock_dependencies();
+
+        let validators: Vec<Validator> = vec![Validator {
+            address: "val1".to_string(),
+            commission: Decimal::percent(5),
+            max_commission: Decimal::percent(10),
+            max_change_rate: Decimal::percent(1),
+        }];
+        deps.querier.update_staking("ustake", &validators, &[]);
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let set_validators = |chain_unbonding_period: u64| ExecuteMsg::SetValidators {
+            validators: vec!["val1".to_string()],
+            chain_unbonding_period: Uint64::from(chain_unbonding_period),
+        };
+
+        // the chain bonds another denom than the contract stakes
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            set_validators(100),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidBondedDenom {}, err);
+
+        deps.querier.update_staking("nanomobx", &validators, &[]);
+
+        // tranches would mature before the chain pays out the undelegated stake
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            set_validators(101),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::UnbondingPeriodTooShort {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            set_validators(100),
+        )
+        .unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetUnbondingPeriod {
+                unbonding_period: Uint64::from(99u64),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::UnbondingPeriodTooShort {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env,
+            mock_info("creator", &[]),
+            ExecuteMsg::SetUnbondingPeriod {
+                unbonding_period: Uint64::from(200u64),
+            },
+        )
+        .unwrap();
+    }
+
+    const TEST_STAKING_MODULE: &str = "staking_module";
+    const TEST_DELEGATIONS: Map<(&Addr, &str), Uint128> = Map::new("test_delegations");
+    const TEST_UNBONDINGS: Map<(&Addr, u64), Uint128> = Map::new("test_unbondings");
+
+    // the delegation query response, which cosmwasm-std doesn't export
+    #[derive(Serialize)]
+    struct ChainDelegationResponse {
+        delegation: Option<FullDelegation>,
+    }
+
+    // cw-multi-test only ships a failing staking module, this one keeps the delegated coins in
+    // its own account and pays undelegations out once the chain unbonding period has passed
+    struct TestStaking {
+        validators: Vec<Validator>,
+        unbonding_period: u64, // in seconds
+    }
+
+    impl TestStaking {
+        fn complete_unbondings<ExecC, QueryC>(
+            &self,
+            api: &dyn Api,
+            storage: &mut dyn Storage,
+            router: &dyn CosmosRouter<ExecC = ExecC, QueryC = QueryC>,
+            block: &BlockInfo,
+        ) -> AnyResult<()>
+        where
+            ExecC: std::fmt::Debug + Clone + PartialEq + JsonSchema + DeserializeOwned + 'static,
+            QueryC: CustomQuery + DeserializeOwned + 'static,
+        {
+            let matured: Vec<((Addr, u64), Uint128)> = TEST_UNBONDINGS
+                .range(storage, None, None, Order::Ascending)
+                .filter(|item| {
+                    item.as_ref().map_or(true, |((_, completion), _)| {
+                        *completion <= block.time.nanos()
+                    })
+                })
+                .collect::<StdResult<_>>()?;
+
+            for ((delegator, completion), amount) in matured {
+                router.execute(
+                    api,
+                    storage,
+                    block,
+                    Addr::unchecked(TEST_STAKING_MODULE),
+                    BankMsg::Send {
+                        to_address: delegator.to_string(),
+                        amount: coins(amount.u128(), "nanomobx"),
+                    }
+                    .into(),
+                )?;
+                TEST_UNBONDINGS.remove(storage, (&delegator, completion));
+            }
+
+            Ok(())
+        }
+    }
+
+    impl Module for TestStaking {
+        type ExecT = StakingMsg;
+        type QueryT = StakingQuery;
+        type SudoT = StakingSudo;
+
+        fn execute<ExecC, QueryC>(
+            &self,
+            api: &dyn Api,
+            storage: &mut dyn Storage,
+            router: &dyn CosmosRouter<ExecC = ExecC, QueryC = QueryC>,
+            block: &BlockInfo,
+            sender: Addr,
+            msg: StakingMsg,
+        ) -> AnyResult<AppResponse>
+        where
+            ExecC: std::fmt::Debug + Clone + PartialEq + JsonSchema + DeserializeOwned + 'static,
+            QueryC: CustomQuery + DeserializeOwned + 'static,
+        {
+            match msg {
+                StakingMsg::Delegate { validator, amount } => {
+                    router.execute(
+                        api,
+                        storage,
+                        block,
+                        sender.clone(),
+                        BankMsg::Send {
+                            to_address: TEST_STAKING_MODULE.to_string(),
+                            amount: vec![amount.clone()],
+                        }
+                        .into(),
+                    )?;
+                    TEST_DELEGATIONS.update::<_, StdError>(
+                        storage,
+                        (&sender, &validator),
+                        |delegation| Ok(delegation.unwrap_or_default() + amount.amount),
+                    )?;
+                }
+                StakingMsg::Undelegate { validator, amount } => {
+                    let delegation: Uint128 =
+                        TEST_DELEGATIONS.load(storage, (&sender, &validator))?;
+                    TEST_DELEGATIONS.save(
+                        storage,
+                        (&sender, &validator),
+                        &delegation.checked_sub(amount.amount)?,
+                    )?;
+
+                    let completion: u64 = block.time.plus_seconds(self.unbonding_period).nanos();
+                    TEST_UNBONDINGS.update::<_, StdError>(
+                        storage,
+                        (&sender, completion),
+                        |unbonding| Ok(unbonding.unwrap_or_default() + amount.amount),
+                    )?;
+                }
+                msg => return Err(StdError::generic_err(format!("{:?}", msg)).into()),
+            }
+
+            Ok(AppResponse::default())
+        }
+
+        fn sudo<ExecC, QueryC>(
+            &self,
+            _api: &dyn Api,
+            _storage: &mut dyn Storage,
+            _router: &dyn CosmosRouter<ExecC = ExecC, QueryC = QueryC>,
+            _block: &BlockInfo,
+            msg: StakingSudo,
+        ) -> AnyResult<AppResponse>
+        where
+            ExecC: std::fmt::Debug + Clone + PartialEq + JsonSchema + DeserializeOwned + 'static,
+            QueryC: CustomQuery + DeserializeOwned + 'static,
+        {
+            Err(StdError::generic_err(format!("{:?}", msg)).into())
+        }
+
+        fn query(
+            &self,
+            _api: &dyn Api,
+            storage: &dyn Storage,
+            _querier: &dyn Querier,
+            _block: &BlockInfo,
+            request: StakingQuery,
+        ) -> AnyResult<Binary> {
+            let find_validator = |address: &str| -> Option<Validator> {
+                self.validators
+                    .iter()
+                    .find(|validator| validator.address == address)
+                    .cloned()
+            };
+
+            let res: Binary = match request {
+                StakingQuery::BondedDenom {} => to_binary(&BondedDenomResponse {
+                    denom: "nanomobx".to_string(),
+                })?,
+                StakingQuery::AllValidators {} => to_binary(&AllValidatorsResponse {
+                    validators: self.validators.clone(),
+                })?,
+                StakingQuery::Validator { address } => to_binary(&ValidatorResponse {
+                    validator: find_validator(&address),
+                })?,
+                StakingQuery::Delegation {
+                    delegator,
+                    validator,
+                } => {
+                    let delegator: Addr = Addr::unchecked(delegator);
+                    let amount: Uint128 = TEST_DELEGATIONS
+                        .may_load(storage, (&delegator, &validator))?
+                        .unwrap_or_default();
+
+                    to_binary(&ChainDelegationResponse {
+                        delegation: Some(FullDelegation {
+                            delegator,
+                            validator,
+                            amount: coin(amount.u128(), "nanomobx"),
+                            can_redelegate: coin(amount.u128(), "nanomobx"),
+                            accumulated_rewards: vec![],
+                        })
+                        .filter(|_| !amount.is_zero()),
+                    })?
+                }
+                request => return Err(StdError::generic_err(format!("{:?}", request)).into()),
+            };
+
+            Ok(res)
+        }
+    }
+
+    impl Staking for TestStaking {}
+
+    #[test]
+    fn undelegated_stake_unbonds_on_the_chain() {
+        let mut app = AppBuilder::new()
+            .with_staking(TestStaking {
+                validators: vec![Validator {
+                    address: "val1".to_string(),
+                    commission: Decimal::percent(5),
+                    max_commission: Decimal::percent(10),
+                    max_change_rate: Decimal::percent(1),
+                }],
+                unbonding_period: 100,
+            })
+            .build(|router, _, storage| {
+                router
+                    .bank
+                    .init_balance(storage, &Addr::unchecked("alice"), coins(200, "nanomobx"))
+                    .unwrap();
+            });
+
+        let code_id: u64 =
+            app.store_code(Box::new(ContractWrapper::new(execute, instantiate, query)));
+        let contract: Addr = app
+            .instantiate_contract(
+                code_id,
+                Addr::unchecked("creator"),
+                &InstantiateMsg {
+                    denom: "nanomobx".to_string(),
+                    reward_rate: Uint128::zero(),
+                    paused: false,
+                    unbonding_period: Uint64::from(100u64),
+                    staking_token: None,
+                },
+                &[],
+                "staking",
+                None,
+            )
+            .unwrap();
+
+        let add_stake = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+
+        // staked before there are validators, it stays in the contract
+        app.execute_contract(
+            Addr::unchecked("alice"),
+            contract.clone(),
+            &add_stake,
+            &coins(100, "nanomobx"),
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("creator"),
+            contract.clone(),
+            &ExecuteMsg::SetValidators {
+                validators: vec!["val1".to_string()],
+                chain_unbonding_period: Uint64::from(100u64),
+            },
+            &[],
+        )
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("alice"),
+            contract.clone(),
+            &add_stake,
+            &coins(100, "nanomobx"),
+        )
+        .unwrap();
+
+        let balance: Coin = app.wrap().query_balance(&contract, "nanomobx").unwrap();
+        assert_eq!(Uint128::from(100u128), balance.amount);
+
+        // the delegated stake is undelegated first
+        app.update_block(|block| block.time = block.time.plus_seconds(10));
+        app.execute_contract(
+            Addr::unchecked("alice"),
+            contract.clone(),
+            &ExecuteMsg::Unbond {
+                amount: Uint128::from(150u128),
+            },
+            &[],
+        )
+        .unwrap();
+
+        let tranches: Vec<UnbondResponse> = app
+            .wrap()
+            .query_wasm_smart(
+                &contract,
+                &QueryMsg::QueryUnbondEntry {
+                    address: Addr::unchecked("alice"),
+                },
+            )
+            .unwrap();
+        assert_eq!(Uint128::from(100u128), tranches[0].undelegated_amount);
+
+        // only the part that was never delegated can be bonded again
+        let err = app
+            .execute_contract(
+                Addr::unchecked("alice"),
+                contract.clone(),
+                &ExecuteMsg::CancelUnbond {
+                    amount: Uint128::from(60u128),
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            Some(&ContractError::UndelegatedStake {}),
+            err.downcast_ref::<ContractError>()
+        );
+
+        app.execute_contract(
+            Addr::unchecked("alice"),
+            contract.clone(),
+            &ExecuteMsg::CancelUnbond {
+                amount: Uint128::from(50u128),
+            },
+            &[],
+        )
+        .unwrap();
+
+        // nor can the undelegated part be paid out early, the contract doesn't hold it
+        let err = app
+            .execute_contract(
+                Addr::unchecked("alice"),
+                contract.clone(),
+                &ExecuteMsg::RemoveStakeEarly {
+                    amount: Uint128::from(150u128),
+                    recipient: None,
+                },
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(
+            Some(&ContractError::UndelegatedStake {}),
+            err.downcast_ref::<ContractError>()
+        );
+
+        // by the time the tranche has matured the chain has paid the undelegated stake back
+        app.update_block(|block| block.time = block.time.plus_seconds(100));
+        let block: BlockInfo = app.block_info();
+        app.init_modules(|router, api, storage| {
+            router
+                .staking
+                .complete_unbondings(api, storage, router, &block)
+        })
+        .unwrap();
+
+        app.execute_contract(
+            Addr::unchecked("alice"),
+            contract.clone(),
+            &ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+        let balance: Coin = app.wrap().query_balance("alice", "nanomobx").unwrap();
+        assert_eq!(Uint128::from(100u128), balance.amount);
+
+        let balance: Coin = app.wrap().query_balance(&contract, "nanomobx").unwrap();
+        assert_eq!(Uint128::from(100u128), balance.amount);
+    }
+
+    #[test]
+    fn liquid_stake_with_share_token() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(10u128),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(1000, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetShareToken {
+                address: "shares".to_string(),
+            },
+        )
+        .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "shares".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Mint {
+                    recipient: "alice".to_string(),
+                    amount: Uint128::from(100u128),
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+            res.messages[0].msg
+        );
+
+        // the pool earns 100 in 10 seconds
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(10);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryExchangeRate {},
+        )
+        .unwrap();
+        let value: Decimal = from_binary(&res).unwrap();
+
+        assert_eq!(Decimal::percent(200), value);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("bob", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        assert!(res.attributes.contains(&attr("shares", "50")));
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryTotalShares {},
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(150u128), value);
+
+        let receive_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::from(100u128),
+            msg: to_binary(&ReceiveMsg::Unbond {}).unwrap(),
+        });
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("shares", &[]),
+            receive_msg,
+        )
+        .unwrap();
+
+        assert_eq!(
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "shares".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Burn {
+                    amount: Uint128::from(100u128),
+                })
+                .unwrap(),
+                funds: vec![],
+            }),
+            res.messages[0].msg
+        );
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("alice"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(200u128), value[0].unbound_amount);
+    }
+
+    #[test]
+    fn share_price_includes_unfunded_pool_rewards() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(10u128),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetShareToken {
+                address: "shares".to_string(),
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        // the pool earns 100 in 10 seconds, which the empty reserve can't compound
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(10);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("bob", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        assert!(res.attributes.contains(&attr("shares", "50")));
+
+        // alice's shares are worth 2/3 of the stake and of the rewards left in the pool
+        let receive_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::from(100u128),
+            msg: to_binary(&ReceiveMsg::Unbond {}).unwrap(),
+        });
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("shares", &[]),
+            receive_msg,
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("alice"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(133u128), value[0].unbound_amount);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("alice"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(66u128), value);
+
+        // bob's 50 shares keep 67 of stake and 34 of rewards
+        let res = query(deps.as_ref(), new_env, QueryMsg::QueryExchangeRate {}).unwrap();
+        let value: Decimal = from_binary(&res).unwrap();
+
+        assert_eq!(Decimal::from_ratio(101u128, 50u128), value);
+    }
+
+    #[test]
+    fn transfer_stake_to_new_address() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(10u128),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(1000, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(20u128),
+            },
+        )
+        .unwrap();
+
+        let transfer_msg = ExecuteMsg::TransferStake {
+            recipient: "bob".to_string(),
+            amount: Uint128::from(50u128),
+            include_unbonding: true,
+        };
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            transfer_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::TransfersDisabled {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetTransfersEnabled { enabled: true },
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(10);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("alice", &[]),
+            transfer_msg,
+        )
+        .unwrap();
+
+        assert!(res.attributes.contains(&attr("unbonding_amount", "20")));
+
+        // rewards up to the transfer stay with alice, the rest is split 30 to 50
+        new_env.block.time = env.block.time.plus_seconds(20);
+
+        for (address, rewards) in [("alice", 137u128), ("bob", 62u128)] {
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryRewards {
+                    address: Addr::unchecked(address),
+                },
+            )
+            .unwrap();
+            let value: Uint128 = from_binary(&res).unwrap();
+
+            assert_eq!(Uint128::from(rewards), value);
+        }
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("bob"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(20u128), value[0].unbound_amount);
+    }
+
+    #[test]
+    fn reward_schedule_is_integrated_piecewise() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(10u128),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(1000, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardSchedule {
+                reward_schedule: vec![RewardRateSegment {
+                    start_time: env.block.time,
+                    reward_rate: Uint128::from(20u128),
+                }],
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidRewardSchedule {}, err);
+
+        // the rate doubles after 10 seconds and stops after 20
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardSchedule {
+                reward_schedule: vec![
+                    RewardRateSegment {
+                        start_time: env.block.time.plus_seconds(10),
+                        reward_rate: Uint128::from(20u128),
+                    },
+                    RewardRateSegment {
+                        start_time: env.block.time.plus_seconds(20),
+                        reward_rate: Uint128::zero(),
+                    },
+                ],
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(30);
+
+        let res = query(
+            deps.as_ref(),
+            new_env,
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("alice"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(300u128), value);
+
+        // the first update after a segment has started makes it the reward_rate
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(15);
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: None,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+
+        let res = query(deps.as_ref(), new_env, QueryMsg::QueryConfig {}).unwrap();
+        let value: Config = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(20u128), value.reward_rate);
+        assert_eq!(1, value.reward_schedule.len());
+    }
+
+    #[test]
+    fn cancel_unbond_rebonds_newest_tranches() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(10u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let info = mock_info("anyone", &coins(100, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(30u128),
+            },
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(20u128),
+            },
+        )
+        .unwrap();
+
+        // the first tranche has matured by now, only the second one can be cancelled
+        let mut newest_env = mock_env();
+        newest_env.block.time = env.block.time.plus_seconds(12);
+
+        let err = execute(
+            deps.as_mut(),
+            newest_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::CancelUnbond {
+                amount: Uint128::from(30u128),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InsufficientFunds {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            newest_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::CancelUnbond {
+                amount: Uint128::from(15u128),
+            },
+        )
+        .unwrap();
+
+        let user: UserEntry = USERS
+            .load(deps.as_ref().storage, &Addr::unchecked("anyone"))
+            .unwrap();
+        assert_eq!(Uint128::from(65u128), user.amount);
+
+        let res = query(
+            deps.as_ref(),
+            newest_env,
+            QueryMsg::QueryUnbondEntry {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<UnbondResponse> = from_binary(&res).unwrap();
+
+        assert_eq!(2, value.len());
+        assert_eq!(Uint128::from(30u128), value[0].unbound_amount);
+        assert!(value[0].expired);
+        assert_eq!(Uint128::from(5u128), value[1].unbound_amount);
+        assert!(!value[1].expired);
+    }
+
+    #[test]
+    fn unbonding_stake_stops_diluting_rewards() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(2u128),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        for address in ["leaver", "stayer"] {
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(address, &coins(10, "nanomobx")),
+                ExecuteMsg::AddStake {
+                    recipient: None,
+                    lock_duration: None,
+                },
+            )
+            .unwrap();
+        }
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("leaver", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
+
+        // all rewards go to the stake that is still bonded
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("stayer"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+
+        let res = query(deps.as_ref(), new_env, QueryMsg::QueryInvariants {}).unwrap();
+        let value: InvariantsResponse = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value.staked_balance);
+        assert_eq!(Uint128::from(10u128), value.unbonding_balance);
+        assert_eq!(Uint128::from(10u128), value.unbond_entries_total);
+        assert_eq!(1, value.staker_count);
+        assert!(value.holds);
+    }
+
+    #[test]
+    fn claims_are_paid_from_the_reward_reserve() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "uatom".to_string(),
+            reward_rate: Uint128::from(2u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(100, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidFunds {}, err);
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(100, "uatom")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let info = mock_info("anyone", &coins(10, "uatom"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewardReserve {},
+        )
+        .unwrap();
+        let value: RewardReserveResponse = from_binary(&res).unwrap();
+
+        assert_eq!(
+            RewardReserveResponse {
+                reward_reserve: Uint128::from(100u128),
+                outstanding_rewards: Uint128::from(10u128),
+                runway: Some(Uint64::from(45u64)),
+            },
+            value
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: None,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(10, "uatom"),
+            })
+        );
+
+        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(90u128), value.reward_reserve);
+        assert_eq!(Uint128::zero(), value.reward_liabilities);
+
+        // the claimed rewards are no longer owed, the ones emitted since are
+        let mut newest_env = mock_env();
+        newest_env.block.time = new_env.block.time.plus_seconds(5);
+
+        let res = query(deps.as_ref(), newest_env, QueryMsg::QueryRewardReserve {}).unwrap();
+        let value: RewardReserveResponse = from_binary(&res).unwrap();
+
+        assert_eq!(
+            RewardReserveResponse {
+                reward_reserve: Uint128::from(90u128),
+                outstanding_rewards: Uint128::from(10u128),
+                runway: Some(Uint64::from(40u64)),
+            },
+            value
+        );
+    }
+
+    #[test]
+    fn partially_claim_rewards() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(2u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(6, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: None,
+                allow_partial: false,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::NoFundsAvailable {}, err);
+
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: Some(Uint128::from(11u128)),
+                allow_partial: true,
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InsufficientFunds {}, err);
+
+        // only 6 of the 10 earned rewards are funded
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: None,
+                allow_partial: true,
+            },
+        )
+        .unwrap();
 
-    Ok(all_stakers.unwrap())
-}
+        assert_eq!(res.attributes[2], attr("paid", "6"));
+        assert_eq!(res.attributes[3], attr("outstanding", "4"));
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(6, "nanomobx"),
+            })
+        );
 
-fn query_stake(deps: Deps, address: Addr) -> StdResult<Uint128> {
-    let user: UserEntry = USERS.load(deps.storage, &address)?;
-    let unbond: UnbondEntry = UNBOND_ENTRIES
-        .load(deps.storage, &address)
-        .unwrap_or(UnbondEntry {
-            unbound_amount: Uint128::zero(),
-            expiration_timestamp: Uint64::zero(),
-            is_valid: false,
-        });
+        let user: UserEntry = USERS
+            .load(deps.as_ref().storage, &Addr::unchecked("anyone"))
+            .unwrap();
+        assert_eq!(Uint128::from(4u128), user.rewards);
 
-    Ok(user.amount.checked_add(unbond.unbound_amount)?)
-}
+        // the remainder can be claimed once the reserve is topped up
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("creator", &coins(10, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
 
-fn query_rewards(deps: Deps, address: Addr, env: Env) -> StdResult<Uint128> {
-    let user: UserEntry = USERS.load(deps.storage, &address)?;
-    let config: Config = CONFIG.load(deps.storage)?;
-    let state: State = STATE.load(deps.storage)?;
-    if env.block.time.nanos().gt(&state.last_update_time.nanos()) {
-        let rewards = earned(&user, &state, &config, &env).unwrap_or(user.rewards);
-        Ok(rewards)
-    } else {
-        let rewards = user.rewards;
-        Ok(rewards)
+        let res = execute(
+            deps.as_mut(),
+            new_env,
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: Some(Uint128::from(3u128)),
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res.attributes[2], attr("paid", "3"));
+        assert_eq!(res.attributes[3], attr("outstanding", "1"));
     }
-}
 
-fn query_unbond_entries(deps: Deps, address: Addr, env: Env) -> StdResult<UnbondResponse> {
-    let unbond_entries: UnbondEntry = UNBOND_ENTRIES.load(deps.storage, &address)?;
+    #[test]
+    fn stake_and_unbond_emit_position_attributes() {
+        let mut deps = mock_dependencies();
 
-    Ok(UnbondResponse {
-        expiration_timestamp: unbond_entries.expiration_timestamp,
-        unbound_amount: unbond_entries.unbound_amount,
-        is_valid: unbond_entries.is_valid,
-        expired: unbond_entries
-            .expiration_timestamp
-            .le(&Uint64::from(env.block.time.nanos())),
-    })
-}
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1u128),
+            paused: false,
+            unbonding_period: Uint64::from(10u64),
+            staking_token: None,
+        };
 
-fn query_config(deps: Deps) -> StdResult<Config> {
-    let config: Config = CONFIG.load(deps.storage)?;
+        let env = mock_env();
+        let res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
 
-    Ok(config)
-}
+        assert_eq!(res.attributes[0], attr("action", "instantiate"));
+        assert_eq!(res.attributes[1], attr("owner", "creator"));
 
-fn query_state(deps: Deps) -> StdResult<State> {
-    let state: State = STATE.load(deps.storage)?;
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("custodian", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: Some("anyone".to_string()),
+                lock_duration: None,
+            },
+        )
+        .unwrap();
 
-    Ok(state)
-}
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "stake"),
+                attr("sender", "custodian"),
+                attr("amount", "10"),
+                attr("staker", "anyone"),
+                attr("denom", "nanomobx"),
+                attr("user_balance", "10"),
+                attr("total_staked", "10"),
+                attr("reward_per_token", "0"),
+            ]
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{
-        mock_dependencies, mock_dependencies_with_balance, mock_env, mock_info, MOCK_CONTRACT_ADDR,
-    };
-    use cosmwasm_std::{
-        attr, coins, from_binary, BlockInfo, ContractInfo, CosmosMsg, Timestamp, TransactionInfo,
-    };
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(4u128),
+            },
+        )
+        .unwrap();
+
+        let expiration_timestamp: u64 = new_env.block.time.plus_seconds(10).nanos();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "unbond"),
+                attr("sender", "anyone"),
+                attr("amount", "4"),
+                attr("unbond_id", "0"),
+                attr("expiration_timestamp", expiration_timestamp.to_string()),
+                attr("staker", "anyone"),
+                attr("denom", "nanomobx"),
+                attr("user_balance", "6"),
+                attr("total_staked", "6"),
+                attr("reward_per_token", "0.5"),
+            ]
+        );
+    }
 
     #[test]
-    fn proper_initialization() {
-        let mut deps = mock_dependencies();
+    fn reward_program_stops_at_period_finish() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
             reward_rate: Uint128::zero(),
             paused: false,
-            unbonding_period: Uint64::zero(),
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
-
+        let creator_info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), creator_info, msg).unwrap();
 
-        // we can just call .unwrap() to assert this was a success
-        let res = instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
-        assert_eq!(0, res.messages.len());
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
 
-        // it worked, let's query the config
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
-        let value: Config = from_binary(&res).unwrap();
-        assert_eq!(
-            Config {
-                owner: Addr::unchecked("creator"),
-                chief_pausing_officer: Addr::unchecked("creator"),
-                denom: "nanomobx".to_string(),
-                reward_rate: Uint128::zero(),
-                paused: false,
-                unbonding_period: Uint64::zero(),
+        let notify_msg = ExecuteMsg::NotifyRewardAmount {
+            duration: Uint64::from(10u64),
+        };
+
+        // only the owner can fund a reward program
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(100, "nanomobx")),
+            notify_msg.clone(),
+        )
+        .unwrap_err();
+
+        match err {
+            ContractError::Unauthorized {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(100, "nanomobx")),
+            notify_msg,
+        )
+        .unwrap();
+
+        assert_eq!(res.attributes[4], attr("reward_rate", "10"));
+
+        // the whole budget is emitted after 10 seconds and nothing more afterwards
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(20);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
             },
-            value
-        );
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
 
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryState {}).unwrap();
+        assert_eq!(Uint128::from(100u128), value);
+
+        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryState {}).unwrap();
         let value: State = from_binary(&res).unwrap();
-        assert_eq!(
-            State {
-                reward_per_token_stored: Uint128::zero(),
-                last_update_time: env.block.time,
-                staked_balance: Uint128::zero(),
+
+        assert_eq!(Some(env.block.time.plus_seconds(10)), value.period_finish);
+        assert_eq!(Uint128::zero(), value.reward_budget);
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            info,
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
             },
-            value
-        );
+        )
+        .unwrap();
+
+        let mut newest_env = mock_env();
+        newest_env.block.time = env.block.time.plus_seconds(100);
+
+        let res = query(
+            deps.as_ref(),
+            newest_env,
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(100u128), value);
     }
 
     #[test]
-    fn update_config() {
+    fn reward_rate_resumes_after_reward_program() {
         let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
             reward_rate: Uint128::zero(),
             paused: false,
-            unbonding_period: Uint64::zero(),
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
-        let old_config: Config = from_binary(&res).unwrap();
-        assert_eq!(
-            Config {
-                owner: Addr::unchecked("creator"),
-                chief_pausing_officer: Addr::unchecked("creator"),
-                denom: "nanomobx".to_string(),
-                reward_rate: Uint128::zero(),
-                paused: false,
-                unbonding_period: Uint64::zero(),
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("anyone", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
             },
-            old_config
-        );
+        )
+        .unwrap();
 
-        let new_config = Config {
-            owner: old_config.clone().owner,
-            chief_pausing_officer: Addr::unchecked("CPO"),
-            denom: old_config.clone().denom,
-            reward_rate: Uint128::from(1u128),
-            paused: old_config.paused,
-            unbonding_period: Uint64::from(1u64),
-        };
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(100, "nanomobx")),
+            ExecuteMsg::NotifyRewardAmount {
+                duration: Uint64::from(10u64),
+            },
+        )
+        .unwrap();
 
-        let update_config_msg = ExecuteMsg::UpdateConfig {
-            config: new_config.clone(),
-        };
+        // the rate follows from the budget while the program runs
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardRate {
+                reward_rate: Uint128::from(5u128),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::RewardProgramActive {}, err);
+
+        let err = execute(
+            deps.as_mut(),
+            new_env,
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardSchedule {
+                reward_schedule: vec![RewardRateSegment {
+                    start_time: env.block.time.plus_seconds(50),
+                    reward_rate: Uint128::from(5u128),
+                }],
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::RewardProgramActive {}, err);
+
+        // once the program is over the new rate is emitted without an end
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(20);
+
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardRate {
+                reward_rate: Uint128::from(5u128),
+            },
+        )
+        .unwrap();
+
+        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryState {}).unwrap();
+        let value: State = from_binary(&res).unwrap();
+
+        assert_eq!(None, value.period_finish);
+        assert_eq!(Uint128::zero(), value.reward_budget);
+
+        let mut newest_env = mock_env();
+        newest_env.block.time = env.block.time.plus_seconds(120);
+
+        let res = query(
+            deps.as_ref(),
+            newest_env,
+            QueryMsg::QueryRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Uint128 = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(600u128), value);
+
+        // a pending schedule would change the rate in the middle of a new program
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardSchedule {
+                reward_schedule: vec![RewardRateSegment {
+                    start_time: env.block.time.plus_seconds(50),
+                    reward_rate: Uint128::from(10u128),
+                }],
+            },
+        )
+        .unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("creator", &coins(100, "nanomobx")),
+            ExecuteMsg::NotifyRewardAmount {
+                duration: Uint64::from(10u64),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::RewardScheduleActive {}, err);
+
+        // the schedule is cleared first, the empty list is emitted as none
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardSchedule {
+                reward_schedule: vec![],
+            },
+        )
+        .unwrap();
 
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), update_config_msg).unwrap();
+        let previous_reward_schedule: String =
+            format!("{}:10", env.block.time.plus_seconds(50).nanos());
+        assert!(res
+            .attributes
+            .contains(&attr("previous_reward_schedule", previous_reward_schedule)));
+        assert!(res.attributes.contains(&attr("reward_schedule", "none")));
 
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
-        let current_config: Config = from_binary(&res).unwrap();
-        assert_eq!(new_config.clone(), current_config.clone());
-        assert_ne!(old_config.clone(), current_config.clone());
+        let _res = execute(
+            deps.as_mut(),
+            new_env,
+            mock_info("creator", &coins(100, "nanomobx")),
+            ExecuteMsg::NotifyRewardAmount {
+                duration: Uint64::from(10u64),
+            },
+        )
+        .unwrap();
     }
 
     #[test]
-    fn cpo_should_only_update_cpo_and_paused() {
+    fn claim_reward_streams() {
         let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
             reward_rate: Uint128::zero(),
             paused: false,
-            unbonding_period: Uint64::zero(),
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let creator_info = mock_info("creator", &[]);
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
+        let _res = instantiate(deps.as_mut(), env.clone(), creator_info.clone(), msg).unwrap();
 
-        // the owner hires a new CPO
-        let old_config: Config = from_binary(&res).unwrap();
-        let creator_updated_config = Config {
-            owner: old_config.clone().owner,
-            chief_pausing_officer: Addr::unchecked("cpo"),
-            denom: old_config.clone().denom,
-            reward_rate: Uint128::from(1u128),
-            paused: old_config.paused,
-            unbonding_period: Uint64::from(1u64),
-        };
+        // the staking denom is already paid out through the main reward rate
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            creator_info.clone(),
+            ExecuteMsg::SetRewardStream {
+                denom: Denom::Native("nanomobx".to_string()),
+                reward_rate: Uint128::from(1u128),
+            },
+        )
+        .unwrap_err();
 
-        let update_config_msg = ExecuteMsg::UpdateConfig {
-            config: creator_updated_config.clone(),
-        };
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), update_config_msg).unwrap();
+        match err {
+            ContractError::InvalidRewardDenom {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
 
-        // the CPO tries to take over but fails
-        let malicious_cpo_config: Config = Config {
-            owner: Addr::unchecked("cpo"),
-            chief_pausing_officer: Addr::unchecked("cpo2"),
-            denom: "nanomobx".to_string(),
-            reward_rate: Uint128::from(1_000_000_000u128),
-            paused: true,
-            unbonding_period: Uint64::zero(),
-        };
+        let streams = vec![
+            (Denom::Native("upartner".to_string()), 2u128),
+            (Denom::Cw20(Addr::unchecked("partner_token")), 1u128),
+        ];
+        for (denom, reward_rate) in streams {
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                creator_info.clone(),
+                ExecuteMsg::SetRewardStream {
+                    denom,
+                    reward_rate: Uint128::from(reward_rate),
+                },
+            )
+            .unwrap();
+        }
 
-        let update_config_msg = ExecuteMsg::UpdateConfig {
-            config: malicious_cpo_config.clone(),
-        };
+        // the streams only pay out what they were funded with
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("partner", &coins(10, "upartner")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("partner_token", &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "partner".to_string(),
+                amount: Uint128::from(3u128),
+                msg: to_binary(&ReceiveMsg::FundRewards {}).unwrap(),
+            }),
+        )
+        .unwrap();
 
-        let cpo_info = mock_info("cpo", &coins(0, "nanomobx"));
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
         let _res = execute(
             deps.as_mut(),
             env.clone(),
-            cpo_info.clone(),
-            update_config_msg,
+            info.clone(),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
         )
         .unwrap();
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryConfig {}).unwrap();
-        let current_config: Config = from_binary(&res).unwrap();
 
-        assert_ne!(malicious_cpo_config.clone(), current_config.clone());
-        assert_eq!(malicious_cpo_config.paused, current_config.paused);
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(5);
+
+        let res = query(
+            deps.as_ref(),
+            new_env.clone(),
+            QueryMsg::QueryStreamRewards {
+                address: Addr::unchecked("anyone"),
+            },
+        )
+        .unwrap();
+        let value: Vec<StreamRewardResponse> = from_binary(&res).unwrap();
+
         assert_eq!(
-            malicious_cpo_config.chief_pausing_officer,
-            current_config.chief_pausing_officer
+            vec![
+                StreamRewardResponse {
+                    denom: Denom::Native("upartner".to_string()),
+                    rewards: Uint128::from(10u128),
+                },
+                StreamRewardResponse {
+                    denom: Denom::Cw20(Addr::unchecked("partner_token")),
+                    rewards: Uint128::from(5u128),
+                },
+            ],
+            value
         );
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: None,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res.messages.len(), 2);
         assert_eq!(
-            creator_updated_config.unbonding_period,
-            current_config.unbonding_period
+            res.messages[0].msg,
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "anyone".into(),
+                amount: coins(10, "upartner"),
+            })
         );
         assert_eq!(
-            creator_updated_config.reward_rate,
-            current_config.reward_rate
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "partner_token".into(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "anyone".into(),
+                    amount: Uint128::from(3u128),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
         );
-    }
-
-    #[test]
-    fn add_stake() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
-
-        let msg = InstantiateMsg {
-            denom: "nanomobx".to_string(),
-            reward_rate: Uint128::zero(),
-            paused: false,
-            unbonding_period: Uint64::zero(),
-        };
-
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
-        let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
-
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), env.clone(), info, add_stake_msg).unwrap();
 
         let res = query(
             deps.as_ref(),
-            env.clone(),
-            QueryMsg::QueryStake {
+            new_env,
+            QueryMsg::QueryStreamRewards {
                 address: Addr::unchecked("anyone"),
             },
         )
         .unwrap();
-        let value = from_binary(&res).unwrap();
+        let value: Vec<StreamRewardResponse> = from_binary(&res).unwrap();
 
-        assert_eq!(Uint128::from(10u128), value);
+        assert_eq!(Uint128::zero(), value[0].rewards);
+        assert_eq!(Uint128::from(2u128), value[1].rewards);
     }
 
     #[test]
-    fn unbond_and_remove_stake() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+    fn unfunded_stream_accrues_precisely_without_blocking_claims() {
+        let mut deps = mock_dependencies();
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
-            reward_rate: Uint128::zero(),
+            reward_rate: Uint128::from(1u128),
             paused: false,
-            unbonding_period: Uint64::zero(),
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
-
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
 
-        let mut new_env = mock_env();
-        new_env.block.height += 3;
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(1000, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
 
-        let unbond_msg = ExecuteMsg::Unbond {
-            amount: Uint128::from(10 as u128),
+        let set_stream_msg = ExecuteMsg::SetRewardStream {
+            denom: Denom::Native("upartner".to_string()),
+            reward_rate: Uint128::from(1000u128),
         };
-        let _res = execute(deps.as_mut(), new_env.clone(), info.clone(), unbond_msg);
-
-        let res = query(
-            deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryUnbondEntry {
-                address: Addr::unchecked("anyone"),
-            },
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            set_stream_msg.clone(),
         )
         .unwrap();
-        let value: UnbondResponse = from_binary(&res).unwrap();
-
-        assert_eq!(true, value.is_valid);
-        assert_eq!(
-            Uint64::from(new_env.block.time.nanos()),
-            value.expiration_timestamp
-        );
-        assert_eq!(true, value.expired);
 
-        let remove_stake_msg = ExecuteMsg::RemoveStake {};
         let _res = execute(
             deps.as_mut(),
-            new_env.clone(),
-            info.clone(),
-            remove_stake_msg,
+            env.clone(),
+            mock_info("anyone", &coins(1_000_000_000_000_000, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
         )
         .unwrap();
 
+        // 5000 per update is far less than one token per unit of stake
+        let mut new_env = mock_env();
+        for step in 1..=100 {
+            new_env.block.time = env.block.time.plus_seconds(5 * step);
+            let _res = execute(
+                deps.as_mut(),
+                new_env.clone(),
+                mock_info("creator", &[]),
+                set_stream_msg.clone(),
+            )
+            .unwrap();
+        }
+
         let res = query(
             deps.as_ref(),
             new_env.clone(),
-            QueryMsg::QueryUnbondEntry {
+            QueryMsg::QueryStreamRewards {
                 address: Addr::unchecked("anyone"),
             },
         )
         .unwrap();
-        let value: UnbondResponse = from_binary(&res).unwrap();
+        let value: Vec<StreamRewardResponse> = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(500_000u128), value[0].rewards);
 
-        assert_eq!(false, value.is_valid);
+        // the stream has no reserve, the staking denom rewards are paid anyway
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("anyone", &[]),
+            ExecuteMsg::ClaimRewards {
+                recipient: None,
+                amount: None,
+                allow_partial: false,
+            },
+        )
+        .unwrap();
         assert_eq!(
-            Uint64::from(new_env.block.time.nanos()),
-            value.expiration_timestamp
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "anyone".to_string(),
+                amount: coins(500, "nanomobx"),
+            })],
+            res.messages
         );
 
-        assert_eq!(true, value.expired);
-
         let res = query(
             deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryStake {
+            new_env,
+            QueryMsg::QueryStreamRewards {
                 address: Addr::unchecked("anyone"),
             },
         )
         .unwrap();
-        let value = from_binary(&res).unwrap();
-
-        assert_eq!(Uint128::from(0u128), value);
+        let value: Vec<StreamRewardResponse> = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(500_000u128), value[0].rewards);
     }
 
     #[test]
-    fn unbond_twice() {
+    fn pause_and_auth() {
         let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
-            reward_rate: Uint128::zero(),
-            paused: false,
-            unbonding_period: Uint64::zero(),
+            reward_rate: Uint128::from(1u128),
+            paused: true,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
+        let creator_info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        let _res = instantiate(deps.as_mut(), env.clone(), creator_info.clone(), msg).unwrap();
 
         let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            add_stake_msg.clone(),
+        )
+        .unwrap_err();
 
-        let mut new_env = mock_env();
-        // new_env.block.height += 3;
-        new_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 3 * 1_000_000_000);
+        match err {
+            ContractError::ContractPaused {} => {}
+            e => panic!("unexpecter error: {}", e),
+        }
 
-        let unbond_msg = ExecuteMsg::Unbond {
-            amount: Uint128::from(10u128),
-        };
-        let _res = execute(deps.as_mut(), new_env.clone(), info.clone(), unbond_msg);
+        let unpause_msg = ExecuteMsg::Unpause {};
 
-        let res = query(
-            deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryUnbondEntry {
-                address: Addr::unchecked("anyone"),
-            },
+        // Check if Authorization works
+        let auth_err = execute(
+            deps.as_mut(),
+            env.clone(),
+            info.clone(),
+            unpause_msg.clone(),
         )
-        .unwrap();
-        let value: UnbondResponse = from_binary(&res).unwrap();
+        .unwrap_err();
 
-        assert_eq!(true, value.is_valid);
-        assert_eq!(
-            Uint64::from(new_env.block.time.nanos()),
-            value.expiration_timestamp
-        );
-        assert_eq!(true, value.expired);
+        match auth_err {
+            ContractError::Unauthorized {} => {}
+            e => panic!("unexpecter error: {}", e),
+        }
 
-        let remove_stake_msg = ExecuteMsg::RemoveStake {};
         let _res = execute(
             deps.as_mut(),
-            new_env.clone(),
-            info.clone(),
-            remove_stake_msg,
+            env.clone(),
+            creator_info.clone(),
+            unpause_msg,
         )
         .unwrap();
+        let _res = execute(deps.as_mut(), env.clone(), info, add_stake_msg.clone()).unwrap();
 
         let res = query(
             deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryUnbondEntry {
+            env.clone(),
+            QueryMsg::QueryStake {
                 address: Addr::unchecked("anyone"),
             },
         )
         .unwrap();
-        let value: UnbondEntry = from_binary(&res).unwrap();
+        let value = from_binary(&res).unwrap();
+
+        assert_eq!(Uint128::from(10u128), value);
+    }
+
+    #[test]
+    fn test_query_stakers() {
+        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::from(1u128),
+            paused: false,
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
+        };
+
+        let creator_info = mock_info("creator", &coins(1000, "nanomobx"));
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), creator_info.clone(), msg).unwrap();
+
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            creator_info,
+            add_stake_msg.clone(),
+        )
+        .unwrap();
 
-        assert_eq!(false, value.is_valid);
-        assert_eq!(
-            Uint64::from(new_env.block.time.nanos()),
-            value.expiration_timestamp
-        );
+        let info = mock_info("anyone", &coins(10, "nanomobx"));
+        let _res = execute(deps.as_mut(), env.clone(), info, add_stake_msg).unwrap();
 
         let res = query(
             deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryStake {
-                address: Addr::unchecked("anyone"),
+            env.clone(),
+            QueryMsg::QueryStakers {
+                start_after: None,
+                limit: None,
+                filter: None,
             },
         )
         .unwrap();
-        let value = from_binary(&res).unwrap();
-
-        assert_eq!(Uint128::from(0u128), value);
 
-        let second_unbond_msg = ExecuteMsg::Unbond {
-            amount: Uint128::from(10u128),
-        };
-        let err = execute(
-            deps.as_mut(),
-            new_env.clone(),
-            info.clone(),
-            second_unbond_msg,
-        )
-        .unwrap_err();
+        let value: StakersResponse = from_binary(&res).unwrap();
 
-        match err {
-            ContractError::NoRecordAvailable {} => {}
-            e => panic!("unexpected error: {}", e),
-        }
+        assert_eq!(None, value.next_key);
+        assert_eq!(
+            vec![
+                (
+                    Addr::unchecked("anyone"),
+                    UserEntry {
+                        amount: Uint128::from(10u128),
+                        rewards: Uint128::zero(),
+                        user_reward_per_token_paid: Decimal256::zero(),
+                        stream_rewards: vec![],
+                        auto_compound: false,
+                        boost: Uint128::zero(),
+                    }
+                ),
+                (
+                    Addr::unchecked("creator"),
+                    UserEntry {
+                        amount: Uint128::from(1000u128),
+                        rewards: Uint128::zero(),
+                        user_reward_per_token_paid: Decimal256::zero(),
+                        stream_rewards: vec![],
+                        auto_compound: false,
+                        boost: Uint128::zero(),
+                    }
+                )
+            ],
+            value.stakers
+        );
     }
 
     #[test]
-    fn unbond_period() {
+    fn paginate_and_filter_stakers() {
         let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
             reward_rate: Uint128::zero(),
             paused: false,
-            unbonding_period: Uint64::from(300u64),
+            unbonding_period: Uint64::from(1u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
 
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+        for (address, amount) in [("user1", 10u128), ("user2", 20u128), ("user3", 30u128)] {
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(address, &coins(amount, "nanomobx")),
+                ExecuteMsg::AddStake {
+                    recipient: None,
+                    lock_duration: None,
+                },
+            )
+            .unwrap();
+        }
 
-        let mut new_env = mock_env();
-        new_env.block.height += 3;
+        // user2 unbonds part of the stake, user3 all of it
+        for (address, amount) in [("user2", 5u128), ("user3", 30u128)] {
+            let _res = execute(
+                deps.as_mut(),
+                env.clone(),
+                mock_info(address, &[]),
+                ExecuteMsg::Unbond {
+                    amount: Uint128::from(amount),
+                },
+            )
+            .unwrap();
+        }
 
-        let unbond_msg = ExecuteMsg::Unbond {
-            amount: Uint128::from(10 as u128),
-        };
-        let _res = execute(deps.as_mut(), new_env.clone(), info.clone(), unbond_msg);
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryStakerCount {}).unwrap();
+        let value: u64 = from_binary(&res).unwrap();
+
+        assert_eq!(2, value);
 
         let res = query(
             deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryUnbondEntry {
-                address: Addr::unchecked("anyone"),
+            env.clone(),
+            QueryMsg::QueryStakers {
+                start_after: None,
+                limit: Some(2),
+                filter: None,
             },
         )
         .unwrap();
-        let value: UnbondResponse = from_binary(&res).unwrap();
-
-        let billion: Uint64 = Uint64::from(10u64.pow(9) as u64);
-        let current_time: Uint64 = Uint64::from(env.block.time.nanos());
-        let expiration_timestamp: Uint64 = current_time
-            .checked_add(Uint64::from(300u64).checked_mul(billion).unwrap())
-            .unwrap();
-        assert_eq!(true, value.is_valid);
-        assert_eq!(expiration_timestamp, value.expiration_timestamp);
-        assert_eq!(false, value.expired);
-
-        let remove_stake_msg = ExecuteMsg::RemoveStake {};
-        let err = execute(
-            deps.as_mut(),
-            new_env.clone(),
-            info.clone(),
-            remove_stake_msg,
-        )
-        .unwrap_err();
+        let value: StakersResponse = from_binary(&res).unwrap();
 
-        match err {
-            ContractError::BondedStake {} => {}
-            e => panic!("unexpecter error: {}", e),
-        }
+        assert_eq!(2, value.stakers.len());
+        assert_eq!(Some(Addr::unchecked("user2")), value.next_key);
 
         let res = query(
             deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryUnbondEntry {
-                address: Addr::unchecked("anyone"),
+            env.clone(),
+            QueryMsg::QueryStakers {
+                start_after: Some("user2".to_string()),
+                limit: Some(2),
+                filter: None,
             },
         )
         .unwrap();
-        let value: UnbondResponse = from_binary(&res).unwrap();
+        let value: StakersResponse = from_binary(&res).unwrap();
 
-        assert_eq!(true, value.is_valid);
-        assert_eq!(expiration_timestamp.clone(), value.expiration_timestamp);
-        assert_eq!(false, value.expired);
+        assert_eq!(1, value.stakers.len());
+        assert_eq!(Addr::unchecked("user3"), value.stakers[0].0);
+        assert_eq!(None, value.next_key);
 
         let res = query(
             deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryStake {
-                address: Addr::unchecked("anyone"),
+            env.clone(),
+            QueryMsg::QueryStakers {
+                start_after: None,
+                limit: None,
+                filter: Some(StakersFilter {
+                    min_amount: Some(Uint128::from(10u128)),
+                    has_pending_rewards: None,
+                    has_unbonding: Some(true),
+                }),
             },
         )
         .unwrap();
-        let value = from_binary(&res).unwrap();
+        let value: StakersResponse = from_binary(&res).unwrap();
 
-        assert_eq!(Uint128::from(10u128), value);
+        assert_eq!(1, value.stakers.len());
+        assert_eq!(Addr::unchecked("user2"), value.stakers[0].0);
+    }
 
-        let mut newest_env = mock_env();
-        // new_env.block.height += 3;
-        newest_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 300 * 1_000_000_000);
+    #[test]
+    fn voting_power_at_height() {
+        let mut deps = mock_dependencies();
+
+        let msg = InstantiateMsg {
+            denom: "nanomobx".to_string(),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
+        };
+
+        let env = mock_env();
+        let height: u64 = env.block.height;
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
 
-        let remove_stake_msg = ExecuteMsg::RemoveStake {};
+        let mut new_env = mock_env();
+        new_env.block.height = height + 1;
         let _res = execute(
             deps.as_mut(),
-            newest_env.clone(),
-            info.clone(),
-            remove_stake_msg,
+            new_env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
         )
         .unwrap();
 
-        let res = query(
-            deps.as_ref(),
-            newest_env.clone(),
-            QueryMsg::QueryUnbondEntry {
-                address: Addr::unchecked("anyone"),
+        new_env.block.height = height + 3;
+        let _res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(40u128),
             },
         )
         .unwrap();
-        let value: UnbondResponse = from_binary(&res).unwrap();
 
-        assert_eq!(false, value.is_valid);
-        assert_eq!(expiration_timestamp.clone(), value.expiration_timestamp);
-        assert_eq!(true, value.expired);
+        // the power at a height is the stake at the start of that block
+        for (at, power) in [(height + 1, 0u128), (height + 2, 100), (height + 4, 60)] {
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryVotingPowerAtHeight {
+                    address: "alice".to_string(),
+                    height: Some(at),
+                },
+            )
+            .unwrap();
+            let value: VotingPowerAtHeightResponse = from_binary(&res).unwrap();
+            assert_eq!(Uint128::from(power), value.power);
+            assert_eq!(at, value.height);
+
+            let res = query(
+                deps.as_ref(),
+                new_env.clone(),
+                QueryMsg::QueryTotalPowerAtHeight { height: Some(at) },
+            )
+            .unwrap();
+            let value: TotalPowerAtHeightResponse = from_binary(&res).unwrap();
+            assert_eq!(Uint128::from(power), value.power);
+        }
 
+        // without a height it is the stake at the start of the current block
         let res = query(
             deps.as_ref(),
-            newest_env.clone(),
-            QueryMsg::QueryStake {
-                address: Addr::unchecked("anyone"),
+            new_env.clone(),
+            QueryMsg::QueryVotingPowerAtHeight {
+                address: "alice".to_string(),
+                height: None,
             },
         )
         .unwrap();
-        let value = from_binary(&res).unwrap();
-
-        assert_eq!(Uint128::from(0u128), value);
-    }
+        let value: VotingPowerAtHeightResponse = from_binary(&res).unwrap();
+        assert_eq!(Uint128::from(100u128), value.power);
+        assert_eq!(height + 3, value.height);
 
-    fn env_at_height(height: u64) -> Env {
-        let time = Timestamp::from_seconds((5u64 * height) + 1u64);
-
-        Env {
-            block: BlockInfo {
-                height,
-                time,
-                chain_id: Default::default(),
-            },
-            contract: ContractInfo {
-                address: Addr::unchecked(MOCK_CONTRACT_ADDR),
+        // the queries are sent the way a cw-dao core contract sends them
+        let msg: QueryMsg =
+            from_slice(br#"{"voting_power_at_height":{"address":"alice","height":null}}"#).unwrap();
+        assert_eq!(
+            QueryMsg::QueryVotingPowerAtHeight {
+                address: "alice".to_string(),
+                height: None,
             },
-            transaction: { Some(TransactionInfo { index: 0 }) },
-        }
+            msg
+        );
+
+        let msg: QueryMsg = from_slice(br#"{"total_power_at_height":{"height":5}}"#).unwrap();
+        assert_eq!(QueryMsg::QueryTotalPowerAtHeight { height: Some(5) }, msg);
     }
 
     #[test]
-    fn check_result_claim_failure_due_to_high_reward_rate() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+    fn stake_changes_are_sent_to_hooks() {
+        let mut deps = mock_dependencies();
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
-            reward_rate: Uint128::from(1_000_000_000u128),
+            reward_rate: Uint128::zero(),
             paused: false,
-            unbonding_period: Uint64::from(1u64),
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
         };
 
-        // create the contract
-        instantiate(
+        let env = mock_env();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+
+        let err = execute(
             deps.as_mut(),
-            env_at_height(1),
-            mock_info("creator", &coins(1000, "nanomobx")),
-            msg,
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::AddHook {
+                addr: "governance".to_string(),
+            },
         )
-        .unwrap();
+        .unwrap_err();
+        assert_eq!(ContractError::Unauthorized {}, err);
 
-        // add a series of stakes
-        execute(
+        let _res = execute(
             deps.as_mut(),
-            env_at_height(2),
-            mock_info("user1", &coins(10, "nanomobx")),
-            ExecuteMsg::AddStake {},
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AddHook {
+                addr: "governance".to_string(),
+            },
         )
         .unwrap();
-        execute(
+
+        let err = execute(
             deps.as_mut(),
-            env_at_height(2),
-            mock_info("user2", &coins(200, "nanomobx")),
-            ExecuteMsg::AddStake {},
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::AddHook {
+                addr: "governance".to_string(),
+            },
         )
-        .unwrap();
-        execute(
+        .unwrap_err();
+        assert_eq!(ContractError::HookAlreadyRegistered {}, err);
+
+        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryHooks {}).unwrap();
+        let value: HooksResponse = from_binary(&res).unwrap();
+        assert_eq!(vec!["governance".to_string()], value.hooks);
+
+        let res = execute(
             deps.as_mut(),
-            env_at_height(2),
-            mock_info("user3", &coins(20000, "nanomobx")),
-            ExecuteMsg::AddStake {},
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
         )
         .unwrap();
 
-        assert_eq!(
-            USERS.may_load(deps.as_ref().storage, &Addr::unchecked("user1")),
-            Ok(Some(UserEntry {
-                amount: Uint128::from(10u128),
-                rewards: Uint128::zero(),
-                user_reward_per_token_paid: Uint128::zero(),
-            }))
-        );
-
-        assert_eq!(
-            USERS.may_load(deps.as_ref().storage, &Addr::unchecked("user2")),
-            Ok(Some(UserEntry {
-                amount: Uint128::from(200u128),
-                rewards: Uint128::zero(),
-                user_reward_per_token_paid: Uint128::zero(),
-            }))
-        );
-
-        assert_eq!(
-            USERS.may_load(deps.as_ref().storage, &Addr::unchecked("user3")),
-            Ok(Some(UserEntry {
-                amount: Uint128::from(20000u128),
-                rewards: Uint128::zero(),
-                user_reward_per_token_paid: Uint128::zero(),
-            }))
-        );
-
-        // trigger calculation of rewards - will all fail because the reward rate is so high
-        assert_eq!(
-            execute(
-                deps.as_mut(),
-                env_at_height(12),
-                mock_info("user1", &[]),
-                ExecuteMsg::ClaimRewards {},
-            ),
-            Err(ContractError::NoFundsAvailable {})
-        );
-        assert_eq!(
-            execute(
-                deps.as_mut(),
-                env_at_height(12),
-                mock_info("user2", &[]),
-                ExecuteMsg::ClaimRewards {},
-            ),
-            Err(ContractError::NoFundsAvailable {})
-        );
-        assert_eq!(
-            execute(
-                deps.as_mut(),
-                env_at_height(12),
-                mock_info("user3", &[]),
-                ExecuteMsg::ClaimRewards {},
-            ),
-            Err(ContractError::NoFundsAvailable {})
-        );
-    }
-
-    #[test]
-    fn claim_rewards() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
-
-        let msg = InstantiateMsg {
-            denom: "nanomobx".to_string(),
-            reward_rate: Uint128::from(1u128),
-            paused: false,
-            unbonding_period: Uint64::from(1u64),
+        let hook_msg = StakeChangedHookMsg::Stake {
+            addr: Addr::unchecked("alice"),
+            amount: Uint128::from(100u128),
         };
+        assert_eq!(
+            vec![SubMsg::new(hook_msg.into_cosmos_msg("governance").unwrap())],
+            res.messages
+        );
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
-        let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
-
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
-
-        let other_info = mock_info("another", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(
+        let res = execute(
             deps.as_mut(),
             env.clone(),
-            other_info.clone(),
-            add_stake_msg,
+            mock_info("alice", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(40u128),
+            },
         )
         .unwrap();
 
+        let hook_msg = StakeChangedHookMsg::Unbond {
+            addr: Addr::unchecked("alice"),
+            amount: Uint128::from(40u128),
+        };
+        assert_eq!(
+            vec![SubMsg::new(hook_msg.into_cosmos_msg("governance").unwrap())],
+            res.messages
+        );
+
         let mut new_env = mock_env();
-        new_env.block.height += 4;
-        new_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 4 * 1_000_000_000);
+        new_env.block.time = env.block.time.plus_seconds(100);
 
-        let res = query(
-            deps.as_ref(),
+        let res = execute(
+            deps.as_mut(),
             new_env.clone(),
-            QueryMsg::QueryRewards {
-                address: Addr::unchecked("anyone"),
+            mock_info("alice", &[]),
+            ExecuteMsg::RemoveStake {
+                amount: None,
+                recipient: None,
             },
         )
         .unwrap();
-        let value: Uint128 = from_binary(&res).unwrap();
-
-        assert_eq!(Uint128::from(2u128), value);
-
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), new_env.clone(), info.clone(), add_stake_msg).unwrap();
-
-        let res = query(deps.as_ref(), new_env.clone(), QueryMsg::QueryState {}).unwrap();
-        let value: State = from_binary(&res).unwrap();
 
-        assert_eq!(value.last_update_time, new_env.block.time);
-        assert_eq!(value.staked_balance, Uint128::from(30u128));
+        let hook_msg = StakeChangedHookMsg::Remove {
+            addr: Addr::unchecked("alice"),
+            amount: Uint128::from(40u128),
+        };
+        assert_eq!(2, res.messages.len());
+        assert_eq!(
+            SubMsg::new(hook_msg.into_cosmos_msg("governance").unwrap()),
+            res.messages[1]
+        );
 
-        let res = query(
-            deps.as_ref(),
+        let _res = execute(
+            deps.as_mut(),
             new_env.clone(),
-            QueryMsg::QueryRewards {
-                address: Addr::unchecked("anyone"),
+            mock_info("creator", &[]),
+            ExecuteMsg::RemoveHook {
+                addr: "governance".to_string(),
             },
         )
         .unwrap();
-        let value: Uint128 = from_binary(&res).unwrap();
-
-        assert_eq!(Uint128::from(2u128), value);
 
-        let claim_msg = ExecuteMsg::ClaimRewards {};
-        let _res = execute(deps.as_mut(), new_env.clone(), info.clone(), claim_msg);
-
-        let res = query(
-            deps.as_ref(),
+        let err = execute(
+            deps.as_mut(),
             new_env.clone(),
-            QueryMsg::QueryRewards {
-                address: Addr::unchecked("anyone"),
+            mock_info("creator", &[]),
+            ExecuteMsg::RemoveHook {
+                addr: "governance".to_string(),
             },
         )
-        .unwrap();
-        let value: Uint128 = from_binary(&res).unwrap();
+        .unwrap_err();
+        assert_eq!(ContractError::HookNotRegistered {}, err);
 
-        assert_eq!(Uint128::zero(), value);
+        let res = execute(
+            deps.as_mut(),
+            new_env,
+            mock_info("alice", &coins(10, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
+            },
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
     }
 
     #[test]
-    fn claim_rewards_right_after_stake() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+    fn every_bonded_stake_change_is_sent_to_hooks() {
+        let mut deps = mock_dependencies();
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
             reward_rate: Uint128::from(1u128),
             paused: false,
-            unbonding_period: Uint64::from(1u64),
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
         };
 
-        let info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), info.clone(), msg).unwrap();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
 
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(deps.as_mut(), env.clone(), info.clone(), add_stake_msg).unwrap();
+        for msg in [
+            ExecuteMsg::AddHook {
+                addr: "governance".to_string(),
+            },
+            ExecuteMsg::SetTransfersEnabled { enabled: true },
+        ] {
+            let _res = execute(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
+        }
 
-        let mut new_env = mock_env();
-        new_env.block.height += 4;
-        new_env.block.time = Timestamp::from_nanos(env.block.time.nanos() + 4 * 1_000_000_000);
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(100, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
 
-        let res = query(
-            deps.as_ref(),
-            new_env.clone(),
-            QueryMsg::QueryRewards {
-                address: Addr::unchecked("anyone"),
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            ExecuteMsg::AddStake {
+                recipient: None,
+                lock_duration: None,
             },
         )
         .unwrap();
-        let value: Uint128 = from_binary(&res).unwrap();
 
-        assert_eq!(Uint128::from(4u128), value);
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(40u128),
+            },
+        )
+        .unwrap();
 
-        let claim_msg = ExecuteMsg::ClaimRewards {};
-        let res = execute(deps.as_mut(), new_env.clone(), info.clone(), claim_msg).unwrap();
+        let hook_submsg = |hook_msg: StakeChangedHookMsg| {
+            SubMsg::new(hook_msg.into_cosmos_msg("governance").unwrap())
+        };
 
-        assert_eq!(res.attributes.len(), 1);
-        assert_eq!(res.attributes[0], attr("action", "claim"));
+        // cancelled unbonding stake is bonded again
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::CancelUnbond {
+                amount: Uint128::from(10u128),
+            },
+        )
+        .unwrap();
 
         assert_eq!(
-            res.messages[0].msg,
-            CosmosMsg::Bank(BankMsg::Send {
-                to_address: "anyone".into(),
-                amount: coins(4, "nanomobx"),
-            })
+            vec![hook_submsg(StakeChangedHookMsg::Stake {
+                addr: Addr::unchecked("alice"),
+                amount: Uint128::from(10u128),
+            })],
+            res.messages
         );
 
-        let res = query(
-            deps.as_ref(),
+        // 10 rewards over a stake of 70, rounded down
+        let mut new_env = mock_env();
+        new_env.block.time = env.block.time.plus_seconds(10);
+
+        let res = execute(
+            deps.as_mut(),
             new_env.clone(),
-            QueryMsg::QueryRewards {
-                address: Addr::unchecked("anyone"),
+            mock_info("alice", &[]),
+            ExecuteMsg::Compound {},
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![hook_submsg(StakeChangedHookMsg::Stake {
+                addr: Addr::unchecked("alice"),
+                amount: Uint128::from(9u128),
+            })],
+            res.messages
+        );
+
+        let res = execute(
+            deps.as_mut(),
+            new_env.clone(),
+            mock_info("alice", &[]),
+            ExecuteMsg::TransferStake {
+                recipient: "bob".to_string(),
+                amount: Uint128::from(20u128),
+                include_unbonding: false,
             },
         )
         .unwrap();
-        let value: Uint128 = from_binary(&res).unwrap();
 
-        assert_eq!(Uint128::zero(), value);
+        assert_eq!(
+            vec![
+                hook_submsg(StakeChangedHookMsg::Unbond {
+                    addr: Addr::unchecked("alice"),
+                    amount: Uint128::from(20u128),
+                }),
+                hook_submsg(StakeChangedHookMsg::Stake {
+                    addr: Addr::unchecked("bob"),
+                    amount: Uint128::from(20u128),
+                }),
+            ],
+            res.messages
+        );
+
+        // 30 come from the tranche, the other 10 from the bonded stake
+        let res = execute(
+            deps.as_mut(),
+            new_env,
+            mock_info("alice", &[]),
+            ExecuteMsg::RemoveStakeEarly {
+                amount: Uint128::from(40u128),
+                recipient: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(3, res.messages.len());
+        assert_eq!(
+            vec![
+                hook_submsg(StakeChangedHookMsg::Unbond {
+                    addr: Addr::unchecked("alice"),
+                    amount: Uint128::from(10u128),
+                }),
+                hook_submsg(StakeChangedHookMsg::Remove {
+                    addr: Addr::unchecked("alice"),
+                    amount: Uint128::from(40u128),
+                }),
+            ],
+            res.messages[1..]
+        );
     }
 
     #[test]
-    fn pause_and_auth() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+    fn reject_unexpected_funds_and_sweep_foreign_tokens() {
+        let mut deps = mock_dependencies();
 
         let msg = InstantiateMsg {
             denom: "nanomobx".to_string(),
-            reward_rate: Uint128::from(1u128),
-            paused: true,
-            unbonding_period: Uint64::from(1u64),
+            reward_rate: Uint128::zero(),
+            paused: false,
+            unbonding_period: Uint64::from(100u64),
+            staking_token: None,
         };
 
-        let creator_info = mock_info("creator", &coins(1000, "nanomobx"));
         let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), creator_info.clone(), msg).unwrap();
+        let _res = instantiate(deps.as_mut(), env.clone(), mock_info("creator", &[]), msg).unwrap();
 
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let add_stake_msg = ExecuteMsg::AddStake {};
+        let add_stake_msg = ExecuteMsg::AddStake {
+            recipient: None,
+            lock_duration: None,
+        };
+
+        let funds: Vec<Coin> = vec![coin(100, "nanomobx"), coin(30, "uatom")];
         let err = execute(
             deps.as_mut(),
             env.clone(),
-            info.clone(),
+            mock_info("alice", &funds),
             add_stake_msg.clone(),
         )
         .unwrap_err();
+        assert_eq!(ContractError::InvalidFunds {}, err);
 
-        match err {
-            ContractError::ContractPaused {} => {}
-            e => panic!("unexpecter error: {}", e),
-        }
+        let funds: Vec<Coin> = vec![coin(50, "nanomobx"), coin(50, "nanomobx")];
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &funds),
+            add_stake_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidFunds {}, err);
 
-        let new_config = Config {
-            owner: Addr::unchecked("creator"),
-            chief_pausing_officer: Addr::unchecked("CPO"),
-            denom: "nanomobx".to_string(),
-            reward_rate: Uint128::from(1u128),
-            paused: false,
-            unbonding_period: Uint64::from(1u64),
-        };
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &coins(100, "nanomobx")),
+            add_stake_msg,
+        )
+        .unwrap();
 
-        let update_config_msg = ExecuteMsg::UpdateConfig {
-            config: new_config.clone(),
-        };
+        let _res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &coins(50, "nanomobx")),
+            ExecuteMsg::FundRewards {},
+        )
+        .unwrap();
 
-        // Check if Authorization works
-        let auth_err = execute(
+        let err = execute(
             deps.as_mut(),
             env.clone(),
-            info.clone(),
-            update_config_msg.clone(),
+            mock_info("alice", &coins(1, "uatom")),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
         )
         .unwrap_err();
-
-        match auth_err {
-            ContractError::Unauthorized {} => {}
-            e => panic!("unexpecter error: {}", e),
-        }
+        assert_eq!(ContractError::UnexpectedFunds {}, err);
 
         let _res = execute(
             deps.as_mut(),
             env.clone(),
-            creator_info.clone(),
-            update_config_msg,
+            mock_info("alice", &[]),
+            ExecuteMsg::Unbond {
+                amount: Uint128::from(10u128),
+            },
         )
         .unwrap();
-        let _res = execute(deps.as_mut(), env.clone(), info, add_stake_msg.clone()).unwrap();
 
-        let res = query(
-            deps.as_ref(),
+        let _res = execute(
+            deps.as_mut(),
             env.clone(),
-            QueryMsg::QueryStake {
-                address: Addr::unchecked("anyone"),
+            mock_info("creator", &[]),
+            ExecuteMsg::SetRewardStream {
+                denom: Denom::Native("ujuno".to_string()),
+                reward_rate: Uint128::from(1u128),
             },
         )
         .unwrap();
-        let value = from_binary(&res).unwrap();
-
-        assert_eq!(Uint128::from(10u128), value);
-    }
 
-    #[test]
-    fn test_query_stakers() {
-        let mut deps = mock_dependencies_with_balance(&coins(200, "nanomobx"));
+        // 20 nanomobx and all of the uatom were sent without a message
+        deps.querier.update_balance(
+            MOCK_CONTRACT_ADDR,
+            vec![coin(170, "nanomobx"), coin(30, "uatom"), coin(40, "ujuno")],
+        );
 
-        let msg = InstantiateMsg {
-            denom: "nanomobx".to_string(),
-            reward_rate: Uint128::from(1u128),
-            paused: false,
-            unbonding_period: Uint64::from(1u64),
+        let sweep_msg = ExecuteMsg::SweepForeignTokens {
+            denom: Denom::Native("nanomobx".to_string()),
+            recipient: "treasury".to_string(),
         };
 
-        let creator_info = mock_info("creator", &coins(1000, "nanomobx"));
-        let env = mock_env();
-        let _res = instantiate(deps.as_mut(), env.clone(), creator_info.clone(), msg).unwrap();
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("alice", &[]),
+            sweep_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::Unauthorized {}, err);
 
-        let add_stake_msg = ExecuteMsg::AddStake {};
-        let _res = execute(
+        let res = execute(
             deps.as_mut(),
             env.clone(),
-            creator_info,
-            add_stake_msg.clone(),
+            mock_info("creator", &[]),
+            sweep_msg,
         )
         .unwrap();
-
-        let info = mock_info("anyone", &coins(10, "nanomobx"));
-        let _res = execute(deps.as_mut(), env.clone(), info, add_stake_msg).unwrap();
-
-        let res = query(deps.as_ref(), env.clone(), QueryMsg::QueryStakers {}).unwrap();
-
-        let value: Vec<(Addr, UserEntry)> = from_binary(&res).unwrap();
-
         assert_eq!(
-            vec![
-                (
-                    Addr::unchecked("anyone"),
-                    UserEntry {
-                        amount: Uint128::from(10u128),
-                        rewards: Uint128::zero(),
-                        user_reward_per_token_paid: Uint128::zero()
-                    }
-                ),
-                (
-                    Addr::unchecked("creator"),
-                    UserEntry {
-                        amount: Uint128::from(1000u128),
-                        rewards: Uint128::zero(),
-                        user_reward_per_token_paid: Uint128::zero()
-                    }
-                )
-            ],
-            value
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "treasury".to_string(),
+                amount: coins(20, "nanomobx"),
+            }),
+            res.messages[0].msg
         );
+
+        let res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("creator", &[]),
+            ExecuteMsg::SweepForeignTokens {
+                denom: Denom::Native("uatom".to_string()),
+                recipient: "treasury".to_string(),
+            },
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("amount", "30")));
+
+        let err = execute(
+            deps.as_mut(),
+            env,
+            mock_info("creator", &[]),
+            ExecuteMsg::SweepForeignTokens {
+                denom: Denom::Native("ujuno".to_string()),
+                recipient: "treasury".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InvalidSweepDenom {}, err);
     }
 }
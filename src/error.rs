@@ -45,9 +45,107 @@ pub enum ContractError {
     #[error("Cannot unbond 0 nanomobx")]
     ZeroAmountUnbond {},
 
+    #[error("Cannot remove 0 nanomobx")]
+    ZeroAmountRemove {},
+
+    #[error("Cannot cancel the unbonding of 0 nanomobx")]
+    ZeroAmountCancelUnbond {},
+
     #[error("The contract is paused")]
     ContractPaused {},
 
+    #[error("The reward duration has to be greater than 0")]
+    ZeroRewardDuration {},
+
+    #[error("The reward amount is too small for the given duration")]
+    RewardAmountTooLow {},
+
+    #[error("The staking denom can't be used as an additional reward stream")]
+    InvalidRewardDenom {},
+
+    #[error("This token can't be staked in this contract")]
+    InvalidToken {},
+
+    #[error("Cannot migrate from a different contract: {name}")]
+    InvalidContractName { name: String },
+
+    #[error("Cannot migrate from version {stored} to older version {current}")]
+    CannotDowngrade { stored: String, current: String },
+
+    #[error("There is no pending ownership proposal")]
+    NoOwnershipProposal {},
+
+    #[error(
+        "Lock tiers need a duration and a multiplier of at least 1, durations have to be unique"
+    )]
+    InvalidLockTier {},
+
+    #[error("There is no lock tier with this duration")]
+    UnknownLockTier {},
+
+    #[error("The stake is locked")]
+    LockedStake {},
+
+    #[error("The penalty can't be more than 100%")]
+    InvalidPenalty {},
+
+    #[error("Only native stake can be delegated to validators")]
+    DelegationUnsupported {},
+
+    #[error("Validators have to exist and be unique")]
+    InvalidValidators {},
+
+    #[error("The stake is delegated, it has to be unbonded first")]
+    DelegatedStake {},
+
+    #[error("The stake is unbonding on the chain, it can only be withdrawn once it has matured")]
+    UndelegatedStake {},
+
+    #[error("Validators have to bond the denom of the contract")]
+    InvalidBondedDenom {},
+
+    #[error("The unbonding period can't be shorter than the one of the chain")]
+    UnbondingPeriodTooShort {},
+
+    #[error("Stake that gets shares can't be locked")]
+    LockedLiquidStake {},
+
+    #[error("The share token can't be changed once it is set")]
+    ShareTokenAlreadySet {},
+
+    #[error("Stake transfers are disabled")]
+    TransfersDisabled {},
+
+    #[error("Cannot transfer 0 nanomobx")]
+    ZeroAmountTransfer {},
+
+    #[error("Stake can't be transferred to this address")]
+    InvalidRecipient {},
+
+    #[error("The reward schedule has to start in the future and be ordered by start time")]
+    InvalidRewardSchedule {},
+
+    #[error("The reward rate can't be changed while a funded reward program runs")]
+    RewardProgramActive {},
+
+    #[error("The reward schedule has to be cleared before a reward program starts")]
+    RewardScheduleActive {},
+
+    #[error("Only the staking denom can be sent, as a single coin")]
+    InvalidFunds {},
+
+    #[error("This message doesn't take any funds")]
+    UnexpectedFunds {},
+
+    #[error("The tokens of a reward stream can't be swept")]
+    InvalidSweepDenom {},
+
+    #[error("The hook is already registered")]
+    HookAlreadyRegistered {},
+
+    #[error("The hook is not registered")]
+    HookNotRegistered {},
+
     #[error("Not enough expired stake to remove")]
     NotEnoughExpiredStakeToRemove {},
 }
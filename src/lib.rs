@@ -1,4 +1,5 @@
 pub mod contract;
 pub mod error;
+pub mod migrations;
 pub mod msg;
 pub mod state;
@@ -0,0 +1,173 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{
+    Addr, Decimal256, Order, StdError, StdResult, Storage, Timestamp, Uint128, Uint256, Uint64,
+};
+use cw_storage_plus::{Item, Map};
+
+use crate::state::{
+    RewardStream, State, UnbondEntry, UserEntry, NEXT_UNBOND_ID, REWARD_STREAMS, STAKER_COUNT,
+    STAKE_SNAPSHOTS, STATE, TOTAL_STAKE_SNAPSHOTS, UNBOND_ENTRIES, USERS,
+};
+
+// storage layout of the first deployment, which didn't record a contract version
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyUnbondEntry {
+    pub unbound_amount: Uint128,
+    pub expiration_timestamp: Uint64,
+    pub is_valid: bool,
+}
+
+pub const LEGACY_UNBOND_ENTRIES: Map<&Addr, LegacyUnbondEntry> = Map::new("unbond_entries");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LegacyState {
+    pub reward_per_token_stored: Uint128,
+    pub last_update_time: Timestamp,
+    pub staked_balance: Uint128,
+}
+
+pub const LEGACY_STATE: Item<LegacyState> = Item::new("state");
+
+pub fn migrate_from_legacy(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy_state: LegacyState = LEGACY_STATE.load(storage)?;
+    let state: State = State {
+        // still scaled by 10^9 like the users' checkpoints, both are rescaled later on
+        reward_per_token_stored: Decimal256::from_ratio(
+            legacy_state.reward_per_token_stored,
+            1u128,
+        ),
+        last_update_time: legacy_state.last_update_time,
+        staked_balance: legacy_state.staked_balance,
+        period_finish: None,
+        reward_budget: Uint128::zero(),
+        boosted_balance: Uint128::zero(),
+        unbonding_balance: Uint128::zero(),
+        reward_reserve: Uint128::zero(),
+        delegated_balance: Uint128::zero(),
+        total_shares: Uint128::zero(),
+        emission_remainder: Uint128::zero(),
+        reward_dust: Uint256::zero(),
+        reward_liabilities: Uint128::zero(),
+    };
+    STATE.save(storage, &state)?;
+
+    // the single merged unbond entry of every user becomes their first tranche
+    let legacy_entries: Vec<(Addr, LegacyUnbondEntry)> = LEGACY_UNBOND_ENTRIES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+
+    let mut next_unbond_id: u64 = NEXT_UNBOND_ID.may_load(storage)?.unwrap_or_default();
+    for (address, legacy_entry) in legacy_entries {
+        if legacy_entry.is_valid && !legacy_entry.unbound_amount.is_zero() {
+            let entry: UnbondEntry = UnbondEntry {
+                unbound_amount: legacy_entry.unbound_amount,
+                expiration_timestamp: legacy_entry.expiration_timestamp,
+                undelegated_amount: Uint128::zero(),
+            };
+            UNBOND_ENTRIES.save(storage, (&address, next_unbond_id), &entry)?;
+            next_unbond_id += 1;
+        }
+
+        LEGACY_UNBOND_ENTRIES.remove(storage, &address);
+    }
+    NEXT_UNBOND_ID.save(storage, &next_unbond_id)?;
+
+    let streams: Vec<RewardStream> = REWARD_STREAMS.may_load(storage)?.unwrap_or_default();
+    REWARD_STREAMS.save(storage, &streams)?;
+
+    Ok(())
+}
+
+// versions before the unbonding balance was introduced kept unbonding stake in the
+// staked balance, where it diluted the rewards of the bonded stake
+pub fn init_unbonding_balance(storage: &mut dyn Storage) -> StdResult<()> {
+    let mut state: State = STATE.load(storage)?;
+    if !state.unbonding_balance.is_zero() {
+        return Ok(());
+    }
+
+    let mut unbonding_balance: Uint128 = Uint128::zero();
+    for item in UNBOND_ENTRIES.range(storage, None, None, Order::Ascending) {
+        let (_, entry): (_, UnbondEntry) = item?;
+        unbonding_balance = unbonding_balance.checked_add(entry.unbound_amount)?;
+    }
+
+    state.staked_balance = state.staked_balance.checked_sub(unbonding_balance)?;
+    state.unbonding_balance = unbonding_balance;
+
+    STATE.save(storage, &state)
+}
+
+// versions before the staker counter was introduced have to count the stakers once
+pub fn init_staker_count(storage: &mut dyn Storage) -> StdResult<()> {
+    if STAKER_COUNT.may_load(storage)?.is_some() {
+        return Ok(());
+    }
+
+    let mut staker_count: u64 = 0;
+    for item in USERS.range(storage, None, None, Order::Ascending) {
+        let (_, user): (Addr, UserEntry) = item?;
+        if !user.amount.is_zero() {
+            staker_count += 1;
+        }
+    }
+
+    STAKER_COUNT.save(storage, &staker_count)
+}
+
+// versions before the voting power queries only stored the current stake, history starts
+// with the migration
+pub fn init_stake_snapshots(storage: &mut dyn Storage, height: u64) -> StdResult<()> {
+    if TOTAL_STAKE_SNAPSHOTS.may_load(storage)?.is_some() {
+        return Ok(());
+    }
+
+    let users: Vec<(Addr, UserEntry)> = USERS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+
+    for (address, user) in users {
+        if !user.amount.is_zero() {
+            STAKE_SNAPSHOTS.save(storage, &address, &user.amount, height)?;
+        }
+    }
+
+    let state: State = STATE.load(storage)?;
+    TOTAL_STAKE_SNAPSHOTS.save(storage, &state.staked_balance, height)
+}
+
+// versions before 0.3.0 stored reward_per_token, also the one of the reward streams, as an
+// integer scaled by 10^9, which is read as a Decimal256 that is 10^9 times too large
+pub fn rescale_reward_per_token(storage: &mut dyn Storage) -> StdResult<()> {
+    let mut state: State = STATE.load(storage)?;
+    state.reward_per_token_stored = unscale(state.reward_per_token_stored)?;
+    STATE.save(storage, &state)?;
+
+    let mut streams: Vec<RewardStream> = REWARD_STREAMS.may_load(storage)?.unwrap_or_default();
+    for stream in streams.iter_mut() {
+        stream.reward_per_token_stored = unscale(stream.reward_per_token_stored)?;
+    }
+    REWARD_STREAMS.save(storage, &streams)?;
+
+    let users: Vec<(Addr, UserEntry)> = USERS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+
+    for (address, mut user) in users {
+        user.user_reward_per_token_paid = unscale(user.user_reward_per_token_paid)?;
+        for entry in user.stream_rewards.iter_mut() {
+            entry.user_reward_per_token_paid = unscale(entry.user_reward_per_token_paid)?;
+        }
+        USERS.save(storage, &address, &user)?;
+    }
+
+    Ok(())
+}
+
+fn unscale(value: Decimal256) -> StdResult<Decimal256> {
+    Decimal256::from_atomics(value.atomics(), 27)
+        .map_err(|err| StdError::generic_err(err.to_string()))
+}
@@ -1,5 +1,8 @@
-use crate::state::Config;
-use cosmwasm_std::{Addr, Uint128, Uint64};
+use crate::state::{LockTier, PenaltyDestination, RewardRateSegment, UserEntry};
+use cosmwasm_std::{
+    to_binary, Addr, Binary, CosmosMsg, Decimal, StdResult, Uint128, Uint64, WasmMsg,
+};
+use cw20::{Cw20ReceiveMsg, Denom};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -11,28 +14,220 @@ pub struct InstantiateMsg {
     // nanomobx per second
     pub paused: bool,
     pub unbonding_period: Uint64, // in seconds
+    // address of a CW20 token to stake instead of the native denom
+    pub staking_token: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    AddStake {},
-    Unbond { amount: Uint128 },
-    RemoveStake {},
-    ClaimRewards {},
-    UpdateConfig { config: Config },
+    AddStake {
+        recipient: Option<String>,
+        lock_duration: Option<Uint64>, // in seconds, has to match one of the lock tiers
+    },
+    Receive(Cw20ReceiveMsg),
+    Unbond {
+        amount: Uint128,
+    },
+    // moves stake that is still unbonding back into the bonded stake, newest tranches first
+    CancelUnbond {
+        amount: Uint128,
+    },
+    RemoveStake {
+        amount: Option<Uint128>,
+        recipient: Option<String>,
+    },
+    // removes unbonding and then bonded stake right away, minus the early unbond penalty
+    RemoveStakeEarly {
+        amount: Uint128,
+        recipient: Option<String>,
+    },
+    // amount only limits the staking denom rewards, the reward streams are always paid in full
+    ClaimRewards {
+        recipient: Option<String>,
+        amount: Option<Uint128>,
+        // pay out what the reserve can cover instead of failing, the rest stays claimable
+        #[serde(default)]
+        allow_partial: bool,
+    },
+    // moves bonded stake, and optionally all unbonding tranches, to another address
+    TransferStake {
+        recipient: String,
+        amount: Uint128,
+        #[serde(default)]
+        include_unbonding: bool,
+    },
+    Compound {},
+    SetAutoCompound {
+        enabled: bool,
+    },
+    // None resets it to the staker's own address
+    SetWithdrawAddress {
+        address: Option<String>,
+    },
+    // adds the sent staking denom to the reward reserve
+    FundRewards {},
+    // withdraws the rewards of the delegations and distributes them to the stakers
+    Harvest {},
+    NotifyRewardAmount {
+        duration: Uint64,
+    }, // in seconds
+    // adds a new reward stream or changes the rate of an existing one
+    SetRewardStream {
+        denom: Denom,
+        reward_rate: Uint128,
+    },
+    ProposeNewOwner {
+        owner: String,
+    },
+    AcceptOwnership {},
+    CancelOwnershipProposal {},
+    SetChiefPausingOfficer {
+        address: String,
+    },
+    SetRewardRate {
+        reward_rate: Uint128,
+    },
+    SetUnbondingPeriod {
+        unbonding_period: Uint64,
+    }, // in seconds
+    // replaces the future reward rate changes
+    SetRewardSchedule {
+        reward_schedule: Vec<RewardRateSegment>,
+    },
+    // only applies to new locks
+    SetLockTiers {
+        lock_tiers: Vec<LockTier>,
+    },
+    SetEarlyUnbondPenalty {
+        penalty: Decimal,
+        destination: PenaltyDestination,
+    },
+    // new stake is split evenly between the validators, an empty list stops delegating
+    SetValidators {
+        validators: Vec<String>,
+        chain_unbonding_period: Uint64, // in seconds
+    },
+    // from then on new stake goes into a pool and is paid with shares of it in this CW20
+    SetShareToken {
+        address: String,
+    },
+    SetTransfersEnabled {
+        enabled: bool,
+    },
+    // sends coins sent to the contract by mistake to recipient, stake and reward reserve stay
+    SweepForeignTokens {
+        denom: Denom,
+        recipient: String,
+    },
+    // contracts that get a StakeChangedHookMsg when stake is added, unbonded or removed
+    AddHook {
+        addr: String,
+    },
+    RemoveHook {
+        addr: String,
+    },
+    Pause {},
+    Unpause {},
+}
+
+// messages embedded in a CW20 send to this contract
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    Stake {
+        recipient: Option<String>,
+        lock_duration: Option<Uint64>, // in seconds
+    },
+    FundRewards {},
+    NotifyRewardAmount {
+        duration: Uint64,
+    }, // in seconds
+    // sent with the share token, unbonds the pool stake the shares are worth
+    Unbond {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum QueryMsg {
     // GetCount returns the current count as a json-encoded number
-    QueryStake { address: Addr },
-    QueryRewards { address: Addr },
-    QueryUnbondEntry { address: Addr },
+    QueryStake {
+        address: Addr,
+    },
+    QueryRewards {
+        address: Addr,
+    },
+    QueryUnbondEntry {
+        address: Addr,
+    },
+    QueryWithdrawAddress {
+        address: Addr,
+    },
+    QueryLocks {
+        address: Addr,
+    },
     QueryConfig {},
+    QueryOwnershipProposal {},
     QueryState {},
-    QueryStakers {},
+    QueryStakers {
+        start_after: Option<String>,
+        limit: Option<u32>,
+        filter: Option<StakersFilter>,
+    },
+    QueryStakerCount {},
+    // recomputes the totals from every user and tranche, only meant for monitoring
+    QueryInvariants {},
+    QueryRewardReserve {},
+    QueryDelegations {},
+    // pool stake per share, including rewards that will be compounded into the pool
+    QueryExchangeRate {},
+    QueryTotalShares {},
+    // bonded stake at the start of a block, named like the queries of the cw-dao voting modules
+    #[serde(rename = "voting_power_at_height")]
+    QueryVotingPowerAtHeight {
+        address: String,
+        height: Option<u64>,
+    },
+    #[serde(rename = "total_power_at_height")]
+    QueryTotalPowerAtHeight {
+        height: Option<u64>,
+    },
+    QueryHooks {},
+    QueryRewardStreams {},
+    QueryStreamRewards {
+        address: Addr,
+    },
+}
+
+// sent to the hooks, addr is the address whose stake changed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakeChangedHookMsg {
+    Stake { addr: Addr, amount: Uint128 },
+    Unbond { addr: Addr, amount: Uint128 },
+    Remove { addr: Addr, amount: Uint128 },
+}
+
+impl StakeChangedHookMsg {
+    // serialized as the stake_change_hook execute message of the hook contract
+    pub fn into_binary(self) -> StdResult<Binary> {
+        to_binary(&StakeChangedExecuteMsg::StakeChangeHook(self))
+    }
+
+    pub fn into_cosmos_msg<T: Into<String>>(self, contract_addr: T) -> StdResult<CosmosMsg> {
+        Ok(WasmMsg::Execute {
+            contract_addr: contract_addr.into(),
+            msg: self.into_binary()?,
+            funds: vec![],
+        }
+        .into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum StakeChangedExecuteMsg {
+    StakeChangeHook(StakeChangedHookMsg),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -43,9 +238,88 @@ pub enum MigrateMsg {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct UnbondResponse {
+    pub id: u64,
     pub unbound_amount: Uint128,
-    pub expiration_timestamp: Uint64,
-    // unix timestamp when it expires
-    pub is_valid: bool, // whether it was used, this allows for 1:1 mapping between Users and UnbondEntries
+    pub expiration_timestamp: Uint64, // unix timestamp when it expires
     pub expired: bool,
+    // can't be cancelled or removed early, it is still unbonding on the chain
+    pub undelegated_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockResponse {
+    pub id: u64,
+    pub amount: Uint128,
+    pub multiplier: Decimal,
+    pub unlock_timestamp: Uint64, // unix timestamp when it unlocks
+    pub expired: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InvariantsResponse {
+    pub staked_balance: Uint128,
+    pub users_staked: Uint128,
+    pub boosted_balance: Uint128,
+    pub users_boost: Uint128,
+    pub unbonding_balance: Uint128,
+    pub unbond_entries_total: Uint128,
+    pub staker_count: u64,
+    pub users_with_stake: u64,
+    pub reward_reserve: Uint128,
+    pub delegated_balance: Uint128,
+    pub delegations_total: Uint128,
+    pub contract_balance: Uint128, // of the staking denom
+    // the totals match and the contract holds the stake, the unbonding stake and the reserve
+    pub holds: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardReserveResponse {
+    pub reward_reserve: Uint128,
+    pub outstanding_rewards: Uint128, // earned by the stakers but not claimed yet
+    // seconds until the rest of the reserve is emitted at the current reward_rate
+    pub runway: Option<Uint64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DelegationResponse {
+    pub validator: String,
+    pub amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct VotingPowerAtHeightResponse {
+    pub power: Uint128,
+    pub height: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TotalPowerAtHeightResponse {
+    pub power: Uint128,
+    pub height: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HooksResponse {
+    pub hooks: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StreamRewardResponse {
+    pub denom: Denom,
+    pub rewards: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct StakersFilter {
+    pub min_amount: Option<Uint128>,
+    pub has_pending_rewards: Option<bool>,
+    pub has_unbonding: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StakersResponse {
+    pub stakers: Vec<(Addr, UserEntry)>,
+    // pass as start_after to get the next page, None once all stakers have been scanned
+    pub next_key: Option<Addr>,
 }
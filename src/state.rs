@@ -1,18 +1,51 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Timestamp, Uint128, Uint64};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Decimal, Decimal256, Timestamp, Uint128, Uint256, Uint64};
+use cw20::Denom;
+use cw_storage_plus::{Item, Map, SnapshotItem, SnapshotMap, Strategy};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
 pub struct UserEntry {
     pub amount: Uint128,
     pub rewards: Uint128,
-    pub user_reward_per_token_paid: Uint128,
+    pub user_reward_per_token_paid: Decimal256,
+    // bookkeeping for the additional reward streams, in the same order as REWARD_STREAMS
+    #[serde(default)]
+    pub stream_rewards: Vec<UserStreamEntry>,
+    // restake the staking denom rewards whenever the user interacts with the contract
+    #[serde(default)]
+    pub auto_compound: bool,
+    // extra reward weight from the user's active locks, rewards are earned on amount + boost
+    #[serde(default)]
+    pub boost: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+pub struct UserStreamEntry {
+    pub rewards: Uint128,
+    pub user_reward_per_token_paid: Decimal256,
 }
 
 pub const USERS: Map<&Addr, UserEntry> = Map::new("stakes");
 
+// where a user's withdrawals and rewards are sent when no recipient is given
+pub const WITHDRAW_ADDRESSES: Map<&Addr, Addr> = Map::new("withdraw_addresses");
+
+// bonded stake of every user by block height, for the voting power queries
+pub const STAKE_SNAPSHOTS: SnapshotMap<&Addr, Uint128> = SnapshotMap::new(
+    "stake_snapshots",
+    "stake_snapshots__checkpoints",
+    "stake_snapshots__changelog",
+    Strategy::EveryBlock,
+);
+
+// contracts notified with a StakeChangedHookMsg whenever stake is added, unbonded or removed
+pub const HOOKS: Item<Vec<Addr>> = Item::new("hooks");
+
+// number of users with bonded stake
+pub const STAKER_COUNT: Item<u64> = Item::new("staker_count");
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
@@ -23,25 +56,177 @@ pub struct Config {
     // nanomobx per second
     pub paused: bool,
     pub unbonding_period: Uint64, // in seconds
+    // when set, stake and rewards are in this CW20 token instead of the native denom
+    #[serde(default)]
+    pub staking_token: Option<Addr>,
+    // lock periods that can be chosen when staking
+    #[serde(default)]
+    pub lock_tiers: Vec<LockTier>,
+    // share of the stake kept when it is removed before the end of the unbonding period,
+    // it decays linearly with the time left until the stake would have been unbonded
+    #[serde(default)]
+    pub early_unbond_penalty: Decimal,
+    #[serde(default)]
+    pub penalty_destination: PenaltyDestination,
+    // validators the native stake is delegated to, stake stays in the contract when empty
+    #[serde(default)]
+    pub validators: Vec<String>,
+    // unbonding period of the chain in seconds, unbonding_period can't be shorter
+    #[serde(default)]
+    pub chain_unbonding_period: Uint64,
+    // CW20 minted for new stake, which then goes into a pool held by the contract itself
+    #[serde(default)]
+    pub share_token: Option<Addr>,
+    // whether stakers can move their stake to another address with TransferStake
+    #[serde(default)]
+    pub transfers_enabled: bool,
+    // future changes of reward_rate, ordered by start_time, a segment becomes the reward_rate
+    // with the first update after it has started
+    #[serde(default)]
+    pub reward_schedule: Vec<RewardRateSegment>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardRateSegment {
+    pub start_time: Timestamp,
+    pub reward_rate: Uint128, // per second
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PenaltyDestination {
+    Burn {},
+    Treasury { address: Addr },
+    // paid out to the remaining stakers as rewards
+    Redistribute {},
+}
+
+impl Default for PenaltyDestination {
+    fn default() -> Self {
+        PenaltyDestination::Redistribute {}
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockTier {
+    pub duration: Uint64,    // in seconds
+    pub multiplier: Decimal, // reward weight of a locked token, at least 1
 }
 
 pub const CONFIG: Item<Config> = Item::new("config");
 
+// address proposed as the new owner, it becomes the owner once it accepts
+pub const OWNERSHIP_PROPOSAL: Item<Addr> = Item::new("ownership_proposal");
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct UnbondEntry {
     pub unbound_amount: Uint128,
-    pub expiration_timestamp: Uint64,
-    // unix timestamp when it expires
-    pub is_valid: bool, // whether it was used, this allows for 1:1 mapping between Users and UnbondEntries
+    pub expiration_timestamp: Uint64, // unix timestamp when it expires
+    // part that was undelegated for this tranche, the chain only releases it at maturity
+    #[serde(default)]
+    pub undelegated_amount: Uint128,
+}
+
+// every unbond creates its own tranche, keyed by (user, id) so that a user's tranches
+// are iterated in the order they were created
+pub const UNBOND_ENTRIES: Map<(&Addr, u64), UnbondEntry> = Map::new("unbond_tranches");
+
+// id of the next unbonding tranche, shared between all users
+pub const NEXT_UNBOND_ID: Item<u64> = Item::new("next_unbond_id");
+
+// part of a user's stake that can't be unbonded before unlock_timestamp
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LockEntry {
+    pub amount: Uint128,
+    pub multiplier: Decimal,
+    pub unlock_timestamp: Uint64, // unix timestamp in ns
 }
 
-pub const UNBOND_ENTRIES: Map<&Addr, UnbondEntry> = Map::new("unbond_entries");
+pub const LOCKS: Map<(&Addr, u64), LockEntry> = Map::new("locks");
+
+// id of the next lock position, shared between all users
+pub const NEXT_LOCK_ID: Item<u64> = Item::new("next_lock_id");
+
+// boost of the locks ending at a time, keyed by the unlock timestamp in ns, it stops counting in
+// boosted_balance at that time with the first update afterwards
+pub const BOOST_EXPIRIES: Map<u64, Uint128> = Map::new("boost_expiries");
+
+// reward_per_token of the staking denom and of every stream at the time the boost of some locks
+// stopped counting, the owners of the locks are settled up to it when they release them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BoostCheckpoint {
+    pub reward_per_token: Decimal256,
+    pub stream_reward_per_token: Vec<Decimal256>,
+    // boost of the locks ending at this time that haven't been released yet
+    pub unreleased_boost: Uint128,
+}
+
+pub const BOOST_CHECKPOINTS: Map<u64, BoostCheckpoint> = Map::new("boost_checkpoints");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct State {
-    pub reward_per_token_stored: Uint128,
+    // staking denom rewards per unit of weight
+    pub reward_per_token_stored: Decimal256,
     pub last_update_time: Timestamp,
     pub staked_balance: Uint128,
+    // end of the current reward program, rewards are emitted without an end if no program was funded
+    pub period_finish: Option<Timestamp>,
+    pub reward_budget: Uint128, // rewards of the current program that haven't been emitted yet
+    // sum of the users' boosts, rewards are distributed over staked_balance + boosted_balance
+    #[serde(default)]
+    pub boosted_balance: Uint128,
+    // stake waiting in unbonding tranches, it doesn't earn rewards
+    #[serde(default)]
+    pub unbonding_balance: Uint128,
+    // staking denom deposited for rewards that hasn't been paid out yet
+    #[serde(default)]
+    pub reward_reserve: Uint128,
+    // part of the bonded stake that is delegated to validators
+    #[serde(default)]
+    pub delegated_balance: Uint128,
+    // shares of the pool minted as the share token
+    #[serde(default)]
+    pub total_shares: Uint128,
+    // emitted rewards of less than a whole token, in 10^-9 of a token
+    #[serde(default)]
+    pub emission_remainder: Uint128,
+    // credited rewards that reward_per_token_stored is too coarse for, in 10^-18 of a token
+    #[serde(default)]
+    pub reward_dust: Uint256,
+    // rewards credited to the stakers that haven't been paid out or compounded yet
+    #[serde(default)]
+    pub reward_liabilities: Uint128,
 }
 
 pub const STATE: Item<State> = Item::new("state");
+
+// staked_balance by block height, for the total voting power query
+pub const TOTAL_STAKE_SNAPSHOTS: SnapshotItem<Uint128> = SnapshotItem::new(
+    "total_stake_snapshots",
+    "total_stake_snapshots__checkpoints",
+    "total_stake_snapshots__changelog",
+    Strategy::EveryBlock,
+);
+
+// amount delegated to each validator, validators removed from the config stay here until
+// their delegation has been undelegated
+pub const DELEGATIONS: Map<&str, Uint128> = Map::new("delegations");
+
+// a reward token distributed to stakers on top of the staking denom rewards
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RewardStream {
+    pub denom: Denom,
+    pub reward_rate: Uint128, // per second
+    // stream rewards per unit of weight
+    pub reward_per_token_stored: Decimal256,
+    // tokens of the stream deposited for rewards that haven't been paid out yet
+    #[serde(default)]
+    pub reward_reserve: Uint128,
+    // emitted rewards that reward_per_token_stored is too coarse for, in 10^-18 of a token
+    #[serde(default)]
+    pub reward_dust: Uint256,
+}
+
+// streams are never removed (only stopped with a 0 reward_rate), so that their position
+// keeps matching UserEntry.stream_rewards
+pub const REWARD_STREAMS: Item<Vec<RewardStream>> = Item::new("reward_streams");